@@ -0,0 +1,184 @@
+//! Counts SPI calls issued by the driver against a simulated card.
+//!
+//! The byte-at-a-time baseline is the count of calls of a driver transferring
+//! every byte with a separate call, the same as the count of transferred bytes.
+//!
+//! Run with `cargo bench --bench spi_calls`.
+
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind, SimClock},
+    DefaultSdMmcSpiConfig, DiskioDevice, SdMmcSpi, SpiDeviceTransport, Transport,
+};
+
+use embedded_hal_1::spi::{ErrorType, Operation, SpiDevice};
+use std::{cell::Cell, rc::Rc, time::Instant};
+
+const BLOCK_SIZE: usize = 512;
+const BLOCK_COUNT: usize = 8;
+
+/// Discards defmt logs of the driver on the host.
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("");
+
+/// SPI counters.
+#[derive(Default)]
+struct Stats {
+    calls: Cell<usize>,
+    bytes: Cell<usize>,
+}
+
+/// DMA counters.
+#[derive(Default)]
+struct DmaStats {
+    blocks: Cell<usize>,
+}
+
+/// Transport emulating DMA for block payloads.
+struct DmaTransport {
+    inner: SpiDeviceTransport<MockCard>,
+    stats: Rc<DmaStats>,
+}
+
+impl Transport for DmaTransport {
+    type Error = core::convert::Infallible;
+    type SelectError = core::convert::Infallible;
+
+    fn select(&mut self) -> Result<(), Self::SelectError> {
+        self.inner.select()
+    }
+
+    fn unselect(&mut self) -> Result<(), Self::SelectError> {
+        self.inner.unselect()
+    }
+
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.inner.transfer(data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write(data)
+    }
+
+    fn receive_block(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.stats.blocks.set(self.stats.blocks.get() + 1);
+        self.inner.receive_block(data)
+    }
+
+    fn send_block(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.stats.blocks.set(self.stats.blocks.get() + 1);
+        self.inner.send_block(data)
+    }
+}
+
+/// Simulated card counting SPI calls.
+struct MockCard {
+    card: SimCard,
+    stats: Rc<Stats>,
+}
+
+impl MockCard {
+    fn new(stats: Rc<Stats>) -> Self {
+        MockCard {
+            card: SimCard::new(SimCardKind::Sdhc, 2048),
+            stats,
+        }
+    }
+}
+
+impl ErrorType for MockCard {
+    type Error = core::convert::Infallible;
+}
+
+impl SpiDevice for MockCard {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let bytes: usize = operations
+            .iter()
+            .map(|operation| match operation {
+                Operation::Read(buf) => buf.len(),
+                Operation::Write(buf) => buf.len(),
+                Operation::Transfer(read, write) => read.len().max(write.len()),
+                Operation::TransferInPlace(buf) => buf.len(),
+                Operation::DelayNs(_) => 0,
+            })
+            .sum();
+
+        self.stats.calls.set(self.stats.calls.get() + 1);
+        self.stats.bytes.set(self.stats.bytes.get() + bytes);
+
+        self.card.transaction(operations)
+    }
+}
+
+type Sd = SdMmcSpi<SpiDeviceTransport<MockCard>, SimClock, DefaultSdMmcSpiConfig>;
+type DmaSd = SdMmcSpi<DmaTransport, SimClock, DefaultSdMmcSpiConfig>;
+
+fn measure<F: FnOnce()>(stats: &Stats, name: &str, f: F) {
+    let (calls, bytes) = (stats.calls.get(), stats.bytes.get());
+    let start = Instant::now();
+
+    f();
+
+    let bytes = stats.bytes.get() - bytes;
+
+    println!(
+        "{:<24} {:>6} SPI calls {:>6} byte-at-a-time calls {:>8} bytes {:>10.1?}",
+        name,
+        stats.calls.get() - calls,
+        bytes,
+        bytes,
+        start.elapsed()
+    );
+}
+
+fn main() {
+    let stats = Rc::new(Stats::default());
+    let card = MockCard::new(stats.clone());
+    let clock = card.card.clock();
+    let mut sd = Sd::new_device(card, clock);
+    let mut buf = [0u8; BLOCK_SIZE * BLOCK_COUNT];
+
+    measure(&stats, "initialize", || sd.initialize().unwrap());
+    measure(&stats, "read 1 block", || {
+        sd.read(&mut buf[..BLOCK_SIZE], 0).unwrap()
+    });
+    measure(&stats, "read 8 blocks", || sd.read(&mut buf, 0).unwrap());
+    measure(&stats, "write 1 block", || {
+        sd.write(&buf[..BLOCK_SIZE], 0).unwrap()
+    });
+    measure(&stats, "write 8 blocks", || sd.write(&buf, 0).unwrap());
+
+    let stats = Rc::new(Stats::default());
+    let dma_stats = Rc::new(DmaStats::default());
+    let card = MockCard::new(stats.clone());
+    let clock = card.card.clock();
+    let mut sd = DmaSd::with_transport(
+        DmaTransport {
+            inner: SpiDeviceTransport::new(card),
+            stats: dma_stats.clone(),
+        },
+        clock,
+    );
+
+    sd.initialize().unwrap();
+    dma_stats.blocks.set(0);
+
+    measure(&stats, "dma read 8 blocks", || {
+        sd.read(&mut buf, 0).unwrap()
+    });
+    measure(&stats, "dma write 8 blocks", || sd.write(&buf, 0).unwrap());
+
+    println!(
+        "{:<24} {:>6} DMA blocks",
+        "dma total",
+        dma_stats.blocks.get()
+    );
+}
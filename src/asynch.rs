@@ -0,0 +1,281 @@
+use crate::{
+    protocol::{Bus, CardState, Protocol},
+    CapacityProvider, CardType, Cid, ClockControl, Csd, DiskioError, Error, IoctlCmd, Lba, Ocr,
+    Scr, SdMmcSpiConfig, SdStatus, Status, SwitchStatus, TransferError,
+};
+
+use core::{convert::Infallible, marker::PhantomData};
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{Operation, SpiDevice},
+};
+use size::Size;
+
+/// [`AsyncSdMmcSpi`] hardware error type.
+pub type AsyncError<Spi> = Error<<Spi as embedded_hal_async::spi::ErrorType>::Error, Infallible>;
+
+/// Async SD Card SPI driver.
+///
+/// Runs the same init sequence, transfer protocol, retry and recovery policy as
+/// [`SdMmcSpi`](crate::SdMmcSpi). Commands and data blocks are sent in transactions of
+/// the SPI device with the same limits of chip select as
+/// [`SpiDeviceTransport`](crate::SpiDeviceTransport).
+///
+/// `Spi` - async SPI device, chip select is managed by the device.
+/// `Delay` - async delay, used to yield while the card is busy.
+/// `Config` - Config implementation of driver config trait.
+pub struct AsyncSdMmcSpi<Spi: SpiDevice, Delay: DelayNs, Config: SdMmcSpiConfig> {
+    spi: Spi,
+    delay: Delay,
+    clock_control: Option<ClockControl<Spi>>,
+    state: CardState,
+    config: PhantomData<Config>,
+}
+
+impl<Spi: SpiDevice, Delay: DelayNs, Config: SdMmcSpiConfig> AsyncSdMmcSpi<Spi, Delay, Config> {
+    /// Creates a new [`AsyncSdMmcSpi<Spi, Delay, Config>`].
+    ///
+    /// `spi` - async SPI device instance.
+    /// `delay` - async delay instance.
+    pub fn new(spi: Spi, delay: Delay) -> Self {
+        AsyncSdMmcSpi {
+            spi,
+            delay,
+            clock_control: None,
+            state: CardState::new::<Config>(),
+            config: PhantomData::<Config>,
+        }
+    }
+
+    /// Sets callback changing SPI clock frequency.
+    ///
+    /// Called with the init frequency before the init sequence and with the data transfer
+    /// frequency after CSD is read.
+    pub fn with_clock_control(mut self, clock_control: ClockControl<Spi>) -> Self {
+        self.clock_control = Some(clock_control);
+        self
+    }
+
+    /// Get status of card.
+    pub fn status(&self) -> Status {
+        self.state.status.get()
+    }
+
+    /// Card type, available after initialization.
+    pub fn card_type(&self) -> Option<CardType> {
+        self.state.info().map(|info| info.card_type)
+    }
+
+    /// Card Specific Data, available after initialization.
+    pub fn csd(&self) -> Option<&Csd> {
+        self.state.info().map(|info| &info.csd)
+    }
+
+    /// Card capacity in bytes, available after initialization.
+    pub fn card_capacity(&self) -> Option<Size> {
+        self.csd().map(|csd| csd.card_capacity())
+    }
+
+    /// Card capacity in 512-byte blocks, available after initialization.
+    pub fn card_capacity_blocks(&self) -> Option<u64> {
+        self.csd().map(|csd| csd.card_capacity_blocks())
+    }
+
+    /// Card Identification, available after initialization.
+    pub fn cid(&self) -> Option<&Cid> {
+        self.state.info().map(|info| &info.cid)
+    }
+
+    /// Operation Conditions Register, available after initialization.
+    pub fn ocr(&self) -> Option<&Ocr> {
+        self.state.info().map(|info| &info.ocr)
+    }
+
+    /// SD Card Configuration, available after initialization if the card supports SEND_SCR.
+    pub fn scr(&self) -> Option<&Scr> {
+        self.state.info().and_then(|info| info.scr.as_ref())
+    }
+
+    /// Switch function status, available after initialization if the card supports SWITCH_FUNC.
+    pub fn switch_status(&self) -> Option<&SwitchStatus> {
+        self.state
+            .info()
+            .and_then(|info| info.switch_status.as_ref())
+    }
+
+    /// SD Status, available after initialization if the card supports SD_STATUS.
+    pub fn sd_status(&self) -> Option<&SdStatus> {
+        self.state.info().and_then(|info| info.sd_status.as_ref())
+    }
+
+    /// Reset card state, [`AsyncSdMmcSpi::init`] must be called again.
+    pub fn reset(&mut self) {
+        self.state.reset();
+    }
+
+    /// Initialize card.
+    pub async fn init(&mut self) -> Result<(), DiskioError<AsyncError<Spi>>> {
+        self.state.start_init::<Config, _>()?;
+
+        let result = self.protocol().init().await;
+
+        self.state.finish_init::<Config, _, _>(result)
+    }
+
+    /// Read data blocks from card by address.
+    pub async fn read(
+        &mut self,
+        buf: &mut [u8],
+        lba: Lba,
+    ) -> Result<(), DiskioError<AsyncError<Spi>>> {
+        self.read_blocks(buf, lba).await.map_err(|err| err.error)
+    }
+
+    /// Write data blocks to card by address.
+    pub async fn write(
+        &mut self,
+        buf: &[u8],
+        lba: Lba,
+    ) -> Result<(), DiskioError<AsyncError<Spi>>> {
+        self.write_blocks(buf, lba).await.map_err(|err| err.error)
+    }
+
+    /// Read data blocks from the card, on failure reports count of completely read blocks.
+    ///
+    /// Same as [`SdMmcSpi::read_blocks`](crate::SdMmcSpi::read_blocks).
+    pub async fn read_blocks(
+        &mut self,
+        buf: &mut [u8],
+        lba: Lba,
+    ) -> Result<(), TransferError<AsyncError<Spi>>> {
+        self.protocol().read_blocks(buf, lba).await
+    }
+
+    /// Write data blocks to the card, on failure reports count of written blocks.
+    ///
+    /// Same as [`SdMmcSpi::write_blocks`](crate::SdMmcSpi::write_blocks).
+    pub async fn write_blocks(
+        &mut self,
+        buf: &[u8],
+        lba: Lba,
+    ) -> Result<(), TransferError<AsyncError<Spi>>> {
+        self.protocol().write_blocks(buf, lba).await
+    }
+
+    /// Erase blocks from `start_lba` to `end_lba` inclusive.
+    ///
+    /// Same as [`SdMmcSpi::erase`](crate::SdMmcSpi::erase).
+    pub async fn erase(
+        &mut self,
+        start_lba: Lba,
+        end_lba: Lba,
+    ) -> Result<(), DiskioError<AsyncError<Spi>>> {
+        self.protocol().erase(start_lba, end_lba).await
+    }
+
+    /// Complete pending write process.
+    pub async fn sync(&mut self) -> Result<(), DiskioError<AsyncError<Spi>>> {
+        self.protocol().sync().await
+    }
+
+    /// Control the device, same as [`DiskioDevice::ioctl`](crate::DiskioDevice::ioctl)
+    /// of [`SdMmcSpi`](crate::SdMmcSpi).
+    pub async fn ioctl(&mut self, cmd: IoctlCmd<'_>) -> Result<(), DiskioError<AsyncError<Spi>>> {
+        self.protocol().ioctl(cmd).await
+    }
+
+    /// Mutable access to SPI device.
+    pub fn spi_mut(&mut self) -> &mut Spi {
+        &mut self.spi
+    }
+
+    /// Releases SPI device and delay.
+    pub fn release(self) -> (Spi, Delay) {
+        (self.spi, self.delay)
+    }
+
+    /// Protocol on the SPI device.
+    fn protocol(&mut self) -> Protocol<'_, AsyncBus<'_, Spi, Delay>, Config> {
+        let bus = AsyncBus {
+            spi: &mut self.spi,
+            delay: &mut self.delay,
+            clock_control: self.clock_control,
+        };
+
+        Protocol::new(bus, &self.state)
+    }
+}
+
+/// Bus of [`AsyncSdMmcSpi`], chip select is managed by the SPI device.
+struct AsyncBus<'a, Spi, Delay> {
+    spi: &'a mut Spi,
+    delay: &'a mut Delay,
+    clock_control: Option<ClockControl<Spi>>,
+}
+
+impl<Spi: SpiDevice, Delay: DelayNs> Bus for AsyncBus<'_, Spi, Delay> {
+    type Error = Spi::Error;
+    type SelectError = Infallible;
+
+    async fn select(&mut self) -> Result<(), Self::SelectError> {
+        Ok(())
+    }
+
+    async fn unselect(&mut self) -> Result<(), Self::SelectError> {
+        Ok(())
+    }
+
+    async fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.spi.transfer_in_place(data).await
+    }
+
+    async fn command(&mut self, frame: &[u8], response: &mut [u8]) -> Result<(), Self::Error> {
+        response.fill(0xFF);
+        self.spi
+            .transaction(&mut [
+                Operation::Write(frame),
+                Operation::TransferInPlace(response),
+            ])
+            .await
+    }
+
+    async fn receive_data(&mut self, data: &mut [u8], crc: &mut [u8]) -> Result<(), Self::Error> {
+        data.fill(0xFF);
+        crc.fill(0xFF);
+        self.spi
+            .transaction(&mut [
+                Operation::TransferInPlace(data),
+                Operation::TransferInPlace(crc),
+            ])
+            .await
+    }
+
+    async fn send_data(
+        &mut self,
+        token: &[u8],
+        data: &[u8],
+        crc: &[u8],
+        response: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        response.fill(0xFF);
+        self.spi
+            .transaction(&mut [
+                Operation::Write(token),
+                Operation::Write(data),
+                Operation::Write(crc),
+                Operation::TransferInPlace(response),
+            ])
+            .await
+    }
+
+    async fn set_clock(&mut self, hz: u32) -> Result<(), Self::Error> {
+        if let Some(clock_control) = self.clock_control {
+            clock_control(self.spi, hz);
+        }
+        Ok(())
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        self.delay.delay_us(us).await;
+    }
+}
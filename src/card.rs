@@ -0,0 +1,237 @@
+use crate::{
+    config::data_clock_hz,
+    consts::{commands, tokens, BLOCK_SIZE},
+    BlockSize, CapacityProvider, CardType, Cid, Csd, CsdV1, DiskioError, Error, Lba, Ocr, Scr,
+    SdMmcSpiConfig, SdSpecVersion, SdStatus, SwitchStatus, R1, R7,
+};
+
+use defmt::error;
+
+/// Argument of SEND_IF_COND, 2.7-3.6V and check pattern.
+pub(crate) const IF_COND_ARG: u32 =
+    ((R7::VOLTAGE_2V7_3V6 as u32) << 8) | tokens::CMD8_STATUS as u32;
+/// Mask of ACMD23 block count.
+pub(crate) const PRE_ERASE_COUNT_MASK: u32 = 0x007F_FFFF;
+/// SWITCH_FUNC argument, check high speed access mode.
+pub(crate) const SWITCH_CHECK_HIGH_SPEED: u32 = 0x00FF_FFF1;
+/// SWITCH_FUNC argument, switch to high speed access mode.
+pub(crate) const SWITCH_SET_HIGH_SPEED: u32 = 0x80FF_FFF1;
+/// Command class of SWITCH_FUNC.
+const SWITCH_COMMAND_CLASS: u8 = 10;
+
+/// Type, registers and statuses of the card, read by the init sequence.
+#[derive(Clone, Copy)]
+pub(crate) struct CardInfo {
+    pub card_type: CardType,
+    pub ocr: Ocr,
+    pub csd: Csd,
+    pub cid: Cid,
+    pub scr: Option<Scr>,
+    pub switch_status: Option<SwitchStatus>,
+    pub sd_status: Option<SdStatus>,
+}
+
+impl Default for CardInfo {
+    fn default() -> Self {
+        CardInfo {
+            card_type: CardType::SD1,
+            ocr: Ocr(0),
+            csd: Csd::V1(CsdV1(0)),
+            cid: Cid(0),
+            scr: None,
+            switch_status: None,
+            sd_status: None,
+        }
+    }
+}
+
+impl CardInfo {
+    /// Check if `other` is the same card.
+    pub fn is_same_card(&self, other: &CardInfo) -> bool {
+        (self.card_type, self.csd, self.cid) == (other.card_type, other.csd, other.cid)
+    }
+
+    /// SPI clock frequency of data transfer.
+    pub fn clock_hz<Config: SdMmcSpiConfig>(&self) -> u32 {
+        data_clock_hz::<Config>(&self.csd, self.switch_status.as_ref())
+    }
+
+    /// Read and write timeouts, in milliseconds.
+    pub fn timeouts_ms<Config: SdMmcSpiConfig>(&self) -> (u32, u32) {
+        let clock_hz = self.clock_hz::<Config>();
+
+        (
+            self.csd.read_timeout_ms(clock_hz, Config::READ_TIMEOUT_MS),
+            self.csd
+                .write_timeout_ms(clock_hz, Config::WRITE_TIMEOUT_MS),
+        )
+    }
+
+    /// Check if multi-block reads are predefined with SET_BLOCK_COUNT.
+    pub fn supports_set_block_count(&self) -> bool {
+        self.scr.is_some_and(|scr| scr.cmd23_supported())
+    }
+
+    /// Check if multi-block writes are preceded by SET_WR_BLK_ERASE_COUNT.
+    ///
+    /// It is mandatory for SD memory cards of every version, a card without SCR isn't one.
+    pub fn supports_pre_erase(&self) -> bool {
+        self.scr.is_some()
+    }
+
+    /// Timeout of erasing `block_count` blocks, reported by SD Status or the config fallback.
+    pub fn erase_timeout_ms<Config: SdMmcSpiConfig>(&self, block_count: u64) -> u32 {
+        self.sd_status
+            .and_then(|status| status.erase_timeout_ms(block_count))
+            .unwrap_or_else(|| self.fallback_erase_timeout_ms::<Config>(block_count))
+            .max(1)
+    }
+
+    /// Timeout of erasing `block_count` blocks, scaled by the count of erase sectors.
+    fn fallback_erase_timeout_ms<Config: SdMmcSpiConfig>(&self, block_count: u64) -> u32 {
+        let sector_blocks = u64::from(self.csd.erase_sector_blocks().max(1));
+        let timeout_ms = block_count
+            .div_ceil(sector_blocks)
+            .saturating_mul(u64::from(Config::ERASE_TIMEOUT_PER_SECTOR_MS))
+            .max(u64::from(Config::ERASE_TIMEOUT_MS));
+
+        u32::try_from(timeout_ms).unwrap_or(u32::MAX)
+    }
+
+    /// Erase block size, the AU size if the card reports it.
+    pub fn block_size(&self) -> BlockSize {
+        let au_size = self.sd_status.map_or(0, |status| status.au_size_blocks());
+
+        if au_size != 0 {
+            au_size as BlockSize
+        } else {
+            self.csd.erase_sector_blocks() as BlockSize
+        }
+    }
+
+    /// Validate the card can address all blocks of buffer starting at `lba`.
+    pub fn validate_address<T, S>(
+        &self,
+        lba: Lba,
+        buf_len: usize,
+    ) -> Result<(), DiskioError<Error<T, S>>> {
+        let last_lba = lba.checked_add((buf_len / BLOCK_SIZE) as Lba - 1);
+
+        if last_lba
+            .and_then(|last| self.card_type.convert_lba(last))
+            .is_none()
+        {
+            error!(
+                "SD address is out of range, lba: {}, length: {}",
+                lba, buf_len
+            );
+            Err(DiskioError::Hardware(Error::OutOfRange))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Validate erase range.
+    pub fn validate_erase_range<E>(&self, start: Lba, end: Lba) -> Result<(), DiskioError<E>> {
+        if start > end || end >= self.csd.card_capacity_blocks() {
+            error!("SD invalid erase range, start: {}, end: {}", start, end);
+            Err(DiskioError::InvalidArgument)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Validate erase range is aligned to the erase unit.
+    pub fn validate_erase_alignment<E>(&self, start: Lba, end: Lba) -> Result<(), DiskioError<E>> {
+        let unit = Lba::from(self.csd.erase_unit_blocks());
+
+        if !start.is_multiple_of(unit) || !(end + 1).is_multiple_of(unit) {
+            error!(
+                "SD erase range is not aligned, start: {}, end: {}, erase unit: {}",
+                start, end, unit
+            );
+            Err(DiskioError::InvalidArgument)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Erase range of trim, `None` if no erase unit is fully inside the range.
+    ///
+    /// Trim is a hint, partial erase units at the bounds are kept.
+    pub fn trim_range(&self, start: Lba, end: Lba) -> Option<(Lba, Lba)> {
+        let unit = Lba::from(self.csd.erase_unit_blocks());
+        let start = start.next_multiple_of(unit);
+        let end = (end + 1) / unit * unit;
+
+        (start < end).then(|| (start, end - 1))
+    }
+}
+
+/// Validate buffer for read/write.
+pub(crate) fn validate_buffer_len<E>(buf_len: usize) -> Result<(), DiskioError<E>> {
+    if buf_len == 0 || !buf_len.is_multiple_of(BLOCK_SIZE) {
+        error!(
+            "SD invalid buffer, length: {}, block size: {}",
+            buf_len, BLOCK_SIZE
+        );
+        Err(DiskioError::InvalidArgument)
+    } else {
+        Ok(())
+    }
+}
+
+/// Card type by R1 of SEND_IF_COND, `None` if R7 data follows.
+pub(crate) fn if_cond_card_type<T, S>(r1: R1) -> Result<Option<CardType>, Error<T, S>> {
+    if r1 == R1::IN_IDLE_STATE | R1::ILLEGAL_COMMAND {
+        Ok(Some(CardType::SD1))
+    } else if r1 != R1::IN_IDLE_STATE {
+        Err(Error::ErrorCommand(commands::CMD8, r1.into()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Check R1 of READ_OCR, the card may be in idle state.
+pub(crate) fn check_ocr_r1<T, S>(r1: R1) -> Result<(), Error<T, S>> {
+    if (r1 - R1::IN_IDLE_STATE).is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ErrorCommand(commands::CMD58, r1.into()))
+    }
+}
+
+/// Check the card supports the supply voltage of the config.
+pub(crate) fn check_supply_voltage<Config: SdMmcSpiConfig, T, S>(
+    ocr: &Ocr,
+) -> Result<(), Error<T, S>> {
+    if ocr.supports_voltage_mv(Config::SUPPLY_VOLTAGE_MV) {
+        return Ok(());
+    }
+
+    error!(
+        "SD doesn't support supply voltage: {} mV, voltage window: 0x{:03X}",
+        Config::SUPPLY_VOLTAGE_MV,
+        ocr.voltage_window()
+    );
+    Err(Error::UnsupportedVoltage)
+}
+
+/// Check if the card should be switched to high speed mode with SWITCH_FUNC.
+pub(crate) fn should_switch_high_speed<Config: SdMmcSpiConfig>(
+    csd: &Csd,
+    scr: Option<&Scr>,
+) -> bool {
+    Config::HIGH_SPEED
+        && csd.supports_command_class(SWITCH_COMMAND_CLASS)
+        && scr.is_some_and(|scr| scr.spec_version() >= SdSpecVersion::V1_1)
+}
+
+/// Error of the failed init, errors not caused by the card conditions are reported
+/// as [`Error::CardNotFound`].
+pub(crate) fn init_error<T, S>(err: Error<T, S>) -> Error<T, S> {
+    match err {
+        Error::UnsupportedVoltage | Error::CheckPatternMismatch => err,
+        _ => Error::CardNotFound,
+    }
+}
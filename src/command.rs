@@ -0,0 +1,24 @@
+use crate::crc::crc7;
+
+/// Command frame size.
+pub const FRAME_SIZE: usize = 6;
+
+/// Command frame.
+pub type Frame = [u8; FRAME_SIZE];
+
+/// Build command frame with CRC.
+pub fn frame(cmd: u8, arg: u32) -> Frame {
+    let mut buf = [
+        cmd,
+        (arg >> 24) as u8,
+        (arg >> 16) as u8,
+        (arg >> 8) as u8,
+        arg as u8,
+        0,
+    ];
+    let crc_index = buf.len() - 1;
+
+    buf[crc_index] = (crc7(&buf[..crc_index]) << 1) | 0x01;
+
+    buf
+}
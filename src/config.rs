@@ -1,21 +1,82 @@
+use crate::{Csd, Error, SwitchStatus};
+
 /// Represents config for [`SdMmcSpi`](crate::SdMmcSpi).
+///
+/// Timeouts are counted from the time of the bytes transferred at the current SPI clock
+/// and of the delays between polls.
 pub trait SdMmcSpiConfig {
-    /// Max attempts to send command.
-    const CMD_MAX_ATTEMPTS: usize;
     /// Max attempts to read R1.
-    const READ_R1_ATTEMPTS: usize;
+    const READ_R1_ATTEMPTS: usize = 128;
     /// Max attempts to enter SPI mode.
-    const ENTER_SPI_MODE_ATTEMPTS: usize;
-    /// Count of dummy cycles for delay.
-    const DELAY_DUMMY_CYCLES: usize;
+    const ENTER_SPI_MODE_ATTEMPTS: usize = 10;
+    /// Timeout of the card initialization, in milliseconds.
+    const INIT_TIMEOUT_MS: u32 = 1000;
+    /// Max timeout of a block read, in milliseconds.
+    const READ_TIMEOUT_MS: u32 = 100;
+    /// Max timeout of a block write and of waiting for the card to get ready, in milliseconds.
+    ///
+    /// Standard capacity cards use shorter timeouts derived from CSD, if any.
+    const WRITE_TIMEOUT_MS: u32 = 250;
+    /// Min timeout of an erase, in milliseconds.
+    ///
+    /// Used if the card doesn't report the erase timeout in SD Status.
+    const ERASE_TIMEOUT_MS: u32 = 30_000;
+    /// Timeout of erasing an erase sector of CSD, in milliseconds.
+    ///
+    /// Used if the card doesn't report the erase timeout in SD Status, the timeout of an erase
+    /// is scaled by the count of erased sectors, but isn't shorter than `ERASE_TIMEOUT_MS`.
+    const ERASE_TIMEOUT_PER_SECTOR_MS: u32 = 250;
+    /// Supply voltage of the card, in millivolts, must be in the voltage window of OCR.
+    const SUPPLY_VOLTAGE_MV: u16 = 3300;
+    /// SPI clock frequency of the init sequence.
+    const INIT_CLOCK_HZ: u32 = 400_000;
+    /// Max SPI clock frequency of data transfer, the card may limit it further by CSD.
+    const MAX_CLOCK_HZ: u32 = 50_000_000;
+    /// Switch the card to high speed mode, if supported.
+    const HIGH_SPEED: bool = true;
+    /// Delay between polls of the busy card, in microseconds.
+    const POLL_DELAY_US: u32 = 10;
+    /// Max retries of a failed read or write.
+    const TRANSFER_RETRIES: usize = 3;
+    /// Retry a failed read or write from the failed block, otherwise from the first block.
+    const RETRY_FROM_FAILED_BLOCK: bool = true;
+    /// Max re-initializations of a lost card during a read or write, 0 disables the recovery.
+    const RECOVERY_ATTEMPTS: usize = 0;
+
+    /// Check if a read or write failed with the error should be retried.
+    fn is_retryable<T, S>(error: &Error<T, S>) -> bool {
+        matches!(error, Error::CrcError(_, _) | Error::WriteCrcError)
+    }
+
+    /// Check if a read or write failed with the error because the card is lost.
+    fn is_card_lost<T, S>(error: &Error<T, S>) -> bool {
+        matches!(
+            error,
+            Error::TimeoutWaitAvailable | Error::TimeoutCommand(_)
+        )
+    }
 }
 
 /// Default implementation of [`SdMmcSpiConfig`](crate::SdMmcSpiConfig).
 pub struct DefaultSdMmcSpiConfig;
 
-impl SdMmcSpiConfig for DefaultSdMmcSpiConfig {
-    const CMD_MAX_ATTEMPTS: usize = 256;
-    const READ_R1_ATTEMPTS: usize = 128;
-    const ENTER_SPI_MODE_ATTEMPTS: usize = 10;
-    const DELAY_DUMMY_CYCLES: usize = 32;
+impl SdMmcSpiConfig for DefaultSdMmcSpiConfig {}
+
+/// Clock frequency of high speed mode.
+const HIGH_SPEED_CLOCK_HZ: u32 = 50_000_000;
+
+/// SPI clock frequency of data transfer, the init frequency if TRAN_SPEED is reserved.
+pub(crate) fn data_clock_hz<Config: SdMmcSpiConfig>(
+    csd: &Csd,
+    switch_status: Option<&SwitchStatus>,
+) -> u32 {
+    let card_clock_hz = if switch_status.is_some_and(SwitchStatus::is_high_speed) {
+        HIGH_SPEED_CLOCK_HZ
+    } else {
+        csd.max_transfer_rate_hz()
+    };
+
+    card_clock_hz
+        .min(Config::MAX_CLOCK_HZ)
+        .max(Config::INIT_CLOCK_HZ)
 }
@@ -5,10 +5,14 @@ pub mod commands {
     pub const ACMD_FLAG: u8 = 0x80;
     /// GO_IDLE_STATE - init card in spi mode if CS low.
     pub const CMD0: u8 = CMD_BASE;
+    /// SWITCH_FUNC - check or switch card function.
+    pub const CMD6: u8 = CMD_BASE + 6;
     /// SEND_IF_COND - verify SD Memory Card interface operating condition.
     pub const CMD8: u8 = CMD_BASE + 8;
     /// SEND_CSD - read the Card Specific Data (CSD register).
     pub const CMD9: u8 = CMD_BASE + 9;
+    /// SEND_CID - read the Card Identification (CID register).
+    pub const CMD10: u8 = CMD_BASE + 10;
     /// STOP_TRANSMISSION - end multiple block read sequence.
     pub const CMD12: u8 = CMD_BASE + 12;
     /// SEND_STATUS - read the card status register.
@@ -17,19 +21,37 @@ pub mod commands {
     pub const CMD17: u8 = CMD_BASE + 17;
     /// READ_MULTIPLE_BLOCK - read a multiple data blocks from the card.
     pub const CMD18: u8 = CMD_BASE + 18;
+    /// ADDRESS_EXTENSION - set the upper bits of the block address of the next command.
+    pub const CMD22: u8 = CMD_BASE + 22;
+    /// SET_BLOCK_COUNT - set the number of blocks of the next multiple block read or write.
+    pub const CMD23: u8 = CMD_BASE + 23;
     /// WRITE_BLOCK - write a single data block to the card.
     pub const CMD24: u8 = CMD_BASE + 24;
     /// WRITE_MULTIPLE_BLOCK - write blocks of data until a STOP_TRANSMISSION.
     pub const CMD25: u8 = CMD_BASE + 25;
+    /// ERASE_WR_BLK_START_ADDR - set the address of the first block to be erased.
+    pub const CMD32: u8 = CMD_BASE + 32;
+    /// ERASE_WR_BLK_END_ADDR - set the address of the last block to be erased.
+    pub const CMD33: u8 = CMD_BASE + 33;
+    /// ERASE - erase the selected blocks.
+    pub const CMD38: u8 = CMD_BASE + 38;
     /// APP_CMD - escape for application specific command.
     pub const CMD55: u8 = CMD_BASE + 55;
     /// READ_OCR - read the OCR register of a card.
     pub const CMD58: u8 = CMD_BASE + 58;
     /// CRC_ON_OFF - enable or disable CRC checking.
     pub const CMD59: u8 = CMD_BASE + 59;
+    /// SD_STATUS - read the SD Status.
+    pub const ACMD13: u8 = CMD_BASE + ACMD_FLAG + 13;
+    /// SEND_NUM_WR_BLOCKS - read the number of well written blocks.
+    pub const ACMD22: u8 = CMD_BASE + ACMD_FLAG + 22;
+    /// SET_WR_BLK_ERASE_COUNT - set the number of write blocks to be pre-erased before writing.
+    pub const ACMD23: u8 = CMD_BASE + ACMD_FLAG + 23;
     /// SD_SEND_OP_COMD - Sends host capacity support information and activates
     /// the card's initialization process.
     pub const ACMD41: u8 = CMD_BASE + ACMD_FLAG + 41;
+    /// SEND_SCR - read the SD Card Configuration (SCR register).
+    pub const ACMD51: u8 = CMD_BASE + ACMD_FLAG + 51;
 }
 
 pub mod tokens {
@@ -43,12 +65,14 @@ pub mod tokens {
     pub const DATA_RES_MASK: u8 = 0x1F;
     /// Write data accepted token.
     pub const DATA_RES_ACCEPTED: u8 = 0x05;
+    /// Write data rejected due to a CRC error token.
+    pub const DATA_RES_CRC_ERROR: u8 = 0x0B;
+    /// Write data rejected due to a write error token.
+    pub const DATA_RES_WRITE_ERROR: u8 = 0x0D;
     /// Available response token.
     pub const AVAILABLE: u8 = 0xFF;
     /// CMD8 Status token.
     pub const CMD8_STATUS: u8 = 0xAA;
-    /// CMD58 OCR token.
-    pub const CMD58_OCR: u8 = 0xC0;
 }
 
 /// Block size in usize representation.
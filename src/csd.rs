@@ -1,14 +1,39 @@
 use crate::consts::BLOCK_SIZE_U64;
 
 use bitfield::bitfield;
+use defmt::Format;
 use size::{consts::KiB, Size};
 
 /// Card Specific Data block.
 pub type CsdData = [u8; 16];
 
+/// Card Identification block.
+pub type CidData = [u8; 16];
+
+/// SD Card Configuration block.
+pub type ScrData = [u8; 8];
+
+/// Operation Conditions Register block.
+pub type OcrData = [u8; 4];
+
+bitfield! {
+    /// Operation Conditions Register.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Ocr(u32);
+    impl Debug;
+    pub power_up_status, _: 31;
+    pub card_capacity_status, _: 30;
+    pub uhs2_card_status, _: 29;
+    pub card_capacity_over_2tb, _: 27;
+    pub switching_to_18v_accepted, _: 24;
+    pub u16, voltage_window, _: 23, 15;
+}
+
 bitfield! {
     /// Card Specific Data, version 1.
+    #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct CsdV1(u128);
+    impl Debug;
     pub u8, version, _: 127, 126;
     pub u8, data_read_access_time1, _: 119, 112;
     pub u8, data_read_access_time2, _: 111, 104;
@@ -42,7 +67,9 @@ bitfield! {
 
 bitfield! {
     /// Card Specific Data, version 2.
+    #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct CsdV2(u128);
+    impl Debug;
     pub u8, version, _: 127, 126;
     pub u8, data_read_access_time1, _: 119, 112;
     pub u8, data_read_access_time2, _: 111, 104;
@@ -69,10 +96,292 @@ bitfield! {
     pub u8, crc, _: 7, 1;
 }
 
+bitfield! {
+    /// Card Specific Data, version 3, ultra capacity.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct CsdV3(u128);
+    impl Debug;
+    pub u8, version, _: 127, 126;
+    pub u8, data_read_access_time1, _: 119, 112;
+    pub u8, data_read_access_time2, _: 111, 104;
+    pub u8, max_data_transfer_rate, _: 103, 96;
+    pub u16, card_command_classes, _: 95, 84;
+    pub u8, read_block_length, _: 83, 80;
+    pub read_partial_blocks, _: 79;
+    pub write_block_misalignment, _: 78;
+    pub read_block_misalignment, _: 77;
+    pub dsr_implemented, _: 76;
+    pub u32, device_size, _: 75, 48;
+    pub erase_single_block_enabled, _: 46;
+    pub u8, erase_sector_size, _: 45, 39;
+    pub u8, write_protect_group_size, _: 38, 32;
+    pub write_protect_group_enable, _: 31;
+    pub u8, write_speed_factor, _: 28, 26;
+    pub u8, max_write_data_length, _: 25, 22;
+    pub write_partial_blocks_allowed, _: 21;
+    pub file_format_group, _: 15;
+    pub copy_flag, _: 14;
+    pub permanent_write_protection, _: 13;
+    pub temporary_write_protection, _: 12;
+    pub u8, file_format, _: 11, 10;
+    pub u8, crc, _: 7, 1;
+}
+
+bitfield! {
+    /// Card Identification.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Cid(u128);
+    impl Debug;
+    pub u8, manufacturer_id, _: 127, 120;
+    u16, oem_id_raw, _: 119, 104;
+    u64, product_name_raw, _: 103, 64;
+    pub u8, product_revision_major, _: 63, 60;
+    pub u8, product_revision_minor, _: 59, 56;
+    pub u32, serial_number, _: 55, 24;
+    u8, manufacturing_year_raw, _: 19, 12;
+    pub u8, manufacturing_month, _: 11, 8;
+    pub u8, crc, _: 7, 1;
+}
+
+bitfield! {
+    /// SD Card Configuration.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Scr(u64);
+    impl Debug;
+    pub u8, structure, _: 63, 60;
+    pub u8, sd_spec, _: 59, 56;
+    pub data_stat_after_erase, _: 55;
+    pub u8, sd_security, _: 54, 52;
+    pub u8, sd_bus_widths, _: 51, 48;
+    pub sd_spec3, _: 47;
+    pub u8, ex_security, _: 46, 43;
+    pub sd_spec4, _: 42;
+    pub u8, sd_specx, _: 41, 38;
+    pub cmd58_59_supported, _: 35;
+    pub cmd48_49_supported, _: 34;
+    pub cmd23_supported, _: 33;
+    pub cmd20_supported, _: 32;
+}
+
+/// Version of SD Physical Layer Specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub enum SdSpecVersion {
+    /// Version 1.0 and 1.01.
+    V1_0,
+    /// Version 1.10.
+    V1_1,
+    /// Version 2.00.
+    V2,
+    /// Version 3.0x.
+    V3,
+    /// Version 4.xx.
+    V4,
+    /// Version 5.xx.
+    V5,
+    /// Version 6.xx.
+    V6,
+    /// Version 7.xx.
+    V7,
+    /// Version 8.xx.
+    V8,
+    /// Version 9.xx.
+    V9,
+    /// Reserved combination of version fields.
+    Unknown,
+}
+
+impl From<OcrData> for Ocr {
+    fn from(ocr_data: OcrData) -> Self {
+        Ocr(u32::from_be_bytes(ocr_data))
+    }
+}
+
+impl Ocr {
+    /// Low bound of the first voltage range of the window, in mV.
+    const VOLTAGE_WINDOW_MIN_MV: u16 = 2700;
+    /// Width of a voltage range of the window, in mV.
+    const VOLTAGE_RANGE_MV: u16 = 100;
+
+    /// Check if the voltage window includes `mv`, bounds of the ranges are inclusive.
+    pub fn supports_voltage_mv(&self, mv: u16) -> bool {
+        let window = self.voltage_window();
+
+        (0..9).any(|range| {
+            let low = Self::VOLTAGE_WINDOW_MIN_MV + range * Self::VOLTAGE_RANGE_MV;
+
+            (window & (1 << range)) != 0 && (low..=low + Self::VOLTAGE_RANGE_MV).contains(&mv)
+        })
+    }
+}
+
+impl From<ScrData> for Scr {
+    fn from(scr_data: ScrData) -> Self {
+        Scr(u64::from_be_bytes(scr_data))
+    }
+}
+
+impl Scr {
+    /// Returns the version of SD Physical Layer Specification supported by the card.
+    pub fn spec_version(&self) -> SdSpecVersion {
+        match (
+            self.sd_spec(),
+            self.sd_spec3(),
+            self.sd_spec4(),
+            self.sd_specx(),
+        ) {
+            (0, false, false, 0) => SdSpecVersion::V1_0,
+            (1, false, false, 0) => SdSpecVersion::V1_1,
+            (2, false, false, 0) => SdSpecVersion::V2,
+            (2, true, false, 0) => SdSpecVersion::V3,
+            (2, true, true, 0) => SdSpecVersion::V4,
+            (2, true, _, 1) => SdSpecVersion::V5,
+            (2, true, _, 2) => SdSpecVersion::V6,
+            (2, true, _, 3) => SdSpecVersion::V7,
+            (2, true, _, 4) => SdSpecVersion::V8,
+            (2, true, _, 5) => SdSpecVersion::V9,
+            _ => SdSpecVersion::Unknown,
+        }
+    }
+
+    /// Returns the value of erased bytes, 0x00 or 0xFF.
+    pub fn erased_byte(&self) -> u8 {
+        if self.data_stat_after_erase() {
+            0xFF
+        } else {
+            0x00
+        }
+    }
+}
+
 /// Card Specific Data, generic container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Csd {
     V1(CsdV1),
     V2(CsdV2),
+    V3(CsdV3),
+}
+
+impl Csd {
+    /// Read timeout is 100 times the typical read access time.
+    const TIMEOUT_MULTIPLIER: u64 = 100;
+    /// Tenths of TAAC and TRAN_SPEED value.
+    const TIME_VALUES: [u64; 16] = [
+        0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80,
+    ];
+    /// Max R2W_FACTOR, larger values are reserved.
+    const MAX_WRITE_SPEED_FACTOR: u8 = 5;
+
+    /// Returns the erase sector size in 512-byte blocks.
+    pub fn erase_sector_blocks(&self) -> u32 {
+        match self {
+            Csd::V1(csd) => {
+                (u32::from(csd.erase_sector_size()) + 1)
+                    << csd.max_write_data_length().saturating_sub(9)
+            }
+            Csd::V2(csd) => u32::from(csd.erase_sector_size()) + 1,
+            Csd::V3(csd) => u32::from(csd.erase_sector_size()) + 1,
+        }
+    }
+
+    /// Returns the smallest erasable unit in 512-byte blocks.
+    pub fn erase_unit_blocks(&self) -> u32 {
+        let single_block = match self {
+            Csd::V1(csd) => csd.erase_single_block_enabled(),
+            Csd::V2(csd) => csd.erase_single_block_enabled(),
+            Csd::V3(csd) => csd.erase_single_block_enabled(),
+        };
+
+        if single_block {
+            1
+        } else {
+            self.erase_sector_blocks()
+        }
+    }
+
+    /// Check if the card supports command class, CCC bit.
+    pub fn supports_command_class(&self, class: u8) -> bool {
+        let classes = match self {
+            Csd::V1(csd) => csd.card_command_classes(),
+            Csd::V2(csd) => csd.card_command_classes(),
+            Csd::V3(csd) => csd.card_command_classes(),
+        };
+
+        class < 12 && (classes & (1 << class)) != 0
+    }
+
+    /// Returns the max data transfer rate (TRAN_SPEED) in Hz.
+    pub fn max_transfer_rate_hz(&self) -> u32 {
+        let tran_speed = match self {
+            Csd::V1(csd) => csd.max_data_transfer_rate(),
+            Csd::V2(csd) => csd.max_data_transfer_rate(),
+            Csd::V3(csd) => csd.max_data_transfer_rate(),
+        };
+
+        let value = Self::TIME_VALUES[usize::from((tran_speed >> 3) & 0x0F)];
+        let unit = match tran_speed & 0x07 {
+            unit @ 0..=3 => 100_000 * 10u64.pow(u32::from(unit)),
+            _ => 0,
+        };
+
+        (value * unit / 10).min(u64::from(u32::MAX)) as u32
+    }
+
+    /// Returns the typical read access time in nanoseconds, TAAC plus NSAC clock cycles.
+    ///
+    /// `clock_hz` - SPI clock frequency.
+    pub fn read_access_time_ns(&self, clock_hz: u32) -> u64 {
+        let (taac, nsac) = match self {
+            Csd::V1(csd) => (csd.data_read_access_time1(), csd.data_read_access_time2()),
+            Csd::V2(csd) => (csd.data_read_access_time1(), csd.data_read_access_time2()),
+            Csd::V3(csd) => (csd.data_read_access_time1(), csd.data_read_access_time2()),
+        };
+
+        let taac_value = Self::TIME_VALUES[usize::from((taac >> 3) & 0x0F)];
+        let taac_ns = taac_value * 10u64.pow(u32::from(taac & 0x07)) / 10;
+        let nsac_ns = (u64::from(nsac) * 100 * 1_000_000_000).div_ceil(u64::from(clock_hz.max(1)));
+
+        taac_ns + nsac_ns
+    }
+
+    /// Returns the typical block program time in nanoseconds, read access time times R2W_FACTOR.
+    ///
+    /// `clock_hz` - SPI clock frequency.
+    pub fn write_time_ns(&self, clock_hz: u32) -> u64 {
+        let factor = match self {
+            Csd::V1(csd) => csd.write_speed_factor(),
+            Csd::V2(csd) => csd.write_speed_factor(),
+            Csd::V3(csd) => csd.write_speed_factor(),
+        };
+
+        self.read_access_time_ns(clock_hz) << factor.min(Self::MAX_WRITE_SPEED_FACTOR)
+    }
+
+    /// Returns the read timeout in milliseconds, capped at `max_ms`.
+    ///
+    /// Only standard capacity cards derive the timeout from CSD, others always use `max_ms`.
+    pub fn read_timeout_ms(&self, clock_hz: u32, max_ms: u32) -> u32 {
+        match self {
+            Csd::V1(_) => Self::timeout_ms(self.read_access_time_ns(clock_hz), max_ms),
+            Csd::V2(_) | Csd::V3(_) => max_ms,
+        }
+    }
+
+    /// Returns the write timeout in milliseconds, capped at `max_ms`.
+    ///
+    /// Only standard capacity cards derive the timeout from CSD, others always use `max_ms`.
+    pub fn write_timeout_ms(&self, clock_hz: u32, max_ms: u32) -> u32 {
+        match self {
+            Csd::V1(_) => Self::timeout_ms(self.write_time_ns(clock_hz), max_ms),
+            Csd::V2(_) | Csd::V3(_) => max_ms,
+        }
+    }
+
+    /// Timeout of typical operation time, at least 1 ms.
+    fn timeout_ms(typical_ns: u64, max_ms: u32) -> u32 {
+        let timeout_ms = (typical_ns * Self::TIMEOUT_MULTIPLIER).div_ceil(1_000_000);
+
+        timeout_ms.clamp(1, u64::from(max_ms.max(1))) as u32
+    }
 }
 
 /// Represents capacity provider.
@@ -96,11 +405,50 @@ impl From<CsdData> for CsdV2 {
     }
 }
 
+impl From<CsdData> for CsdV3 {
+    fn from(csd_data: CsdData) -> Self {
+        CsdV3(u128::from_be_bytes(csd_data))
+    }
+}
+
+impl From<CidData> for Cid {
+    fn from(cid_data: CidData) -> Self {
+        Cid(u128::from_be_bytes(cid_data))
+    }
+}
+
+impl Cid {
+    /// Returns the OEM/Application ID, two ASCII characters.
+    pub fn oem_id(&self) -> [u8; 2] {
+        self.oem_id_raw().to_be_bytes()
+    }
+
+    /// Returns the product name, five ASCII characters.
+    pub fn product_name(&self) -> [u8; 5] {
+        let bytes = self.product_name_raw().to_be_bytes();
+
+        [bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]
+    }
+
+    /// Returns the manufacturing year.
+    pub fn manufacturing_year(&self) -> u16 {
+        2000 + u16::from(self.manufacturing_year_raw())
+    }
+}
+
+impl From<CsdData> for Csd {
+    fn from(csd_data: CsdData) -> Self {
+        match CsdV1::from(csd_data).version() {
+            0 => Csd::V1(CsdV1::from(csd_data)),
+            2 => Csd::V3(CsdV3::from(csd_data)),
+            _ => Csd::V2(CsdV2::from(csd_data)),
+        }
+    }
+}
+
 impl CapacityProvider for CsdV1 {
     fn card_capacity(&self) -> Size {
-        Size::from_bytes(
-            self.device_size() << (self.device_size_multiplier() + self.read_block_length() + 2),
-        )
+        Size::from_bytes(self.card_capacity_blocks() * BLOCK_SIZE_U64)
     }
 
     fn card_capacity_blocks(&self) -> u64 {
@@ -119,11 +467,22 @@ impl CapacityProvider for CsdV2 {
     }
 }
 
+impl CapacityProvider for CsdV3 {
+    fn card_capacity(&self) -> Size {
+        Size::from_bytes(self.card_capacity_blocks() * BLOCK_SIZE_U64)
+    }
+
+    fn card_capacity_blocks(&self) -> u64 {
+        ((self.device_size() + 1) as u64) * (KiB as u64)
+    }
+}
+
 impl CapacityProvider for Csd {
     fn card_capacity(&self) -> Size {
         match self {
             Csd::V1(csd) => csd.card_capacity(),
             Csd::V2(csd) => csd.card_capacity(),
+            Csd::V3(csd) => csd.card_capacity(),
         }
     }
 
@@ -131,6 +490,7 @@ impl CapacityProvider for Csd {
         match self {
             Csd::V1(csd) => csd.card_capacity_blocks(),
             Csd::V2(csd) => csd.card_capacity_blocks(),
+            Csd::V3(csd) => csd.card_capacity_blocks(),
         }
     }
 }
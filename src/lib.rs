@@ -4,27 +4,55 @@
 
 #![no_std]
 
+#[cfg(feature = "sim")]
+extern crate std;
+
+#[cfg(feature = "async")]
+mod asynch;
+mod card;
+mod command;
 mod config;
 mod consts;
 mod crc;
 mod csd;
+mod protocol;
 mod response;
+#[cfg(feature = "sim")]
+pub mod sim;
+mod transport;
 
+#[cfg(feature = "async")]
+pub use crate::asynch::{AsyncError, AsyncSdMmcSpi};
 pub use crate::config::{DefaultSdMmcSpiConfig, SdMmcSpiConfig};
+pub use crate::csd::{CapacityProvider, Cid, Csd, CsdV1, CsdV2, CsdV3, Ocr, Scr, SdSpecVersion};
+pub use crate::response::{SdStatus, SwitchStatus, R1, R2, R7};
+pub use crate::transport::{
+    ClockControl, SpiBusTransport, SpiDeviceTransport, SpiTransport, Transport,
+};
 pub use diskio::{
     BlockSize, DiskioDevice, Error as DiskioError, IoctlCmd, Lba, Status, StatusFlag,
 };
+pub use size::Size;
 
 use crate::{
-    consts::{commands, tokens, BLOCK_SIZE},
-    crc::{crc16, crc7},
-    csd::{CapacityProvider, Csd, CsdData, CsdV1, CsdV2},
-    response::R1Response,
+    consts::BLOCK_SIZE_U64,
+    protocol::{Bus, CardState, Protocol},
 };
 
-use core::{cell::RefCell, marker::PhantomData};
-use defmt::{error, info, warn, Format};
-use embedded_hal::blocking::spi::Transfer;
+use core::{
+    cell::{RefCell, RefMut},
+    future::Future,
+    marker::PhantomData,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+use defmt::Format;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal_1::{
+    delay::DelayNs,
+    digital::OutputPin,
+    spi::{SpiBus, SpiDevice},
+};
 use switch_hal::OutputSwitch;
 
 /// [`SdMmcSpi`] result error.
@@ -43,10 +71,12 @@ pub enum Error<T, S> {
     TimeoutReadBuffer,
     /// No response when waiting for the card to not be busy.
     TimeoutWaitAvailable,
+    /// No response when waiting for the card to finish erasing.
+    TimeoutErase,
     /// No response when executing this command.
     TimeoutCommand(u8),
-    /// Command error.
-    ErrorCommand(u8),
+    /// Command error (command, card status).
+    ErrorCommand(u8, R2),
     /// Failed to read the Card Specific Data register.
     RegisterReadError,
     /// CRC mismatch (card, host).
@@ -55,18 +85,115 @@ pub enum Error<T, S> {
     ReadError,
     /// Error writing to the card.
     WriteError,
+    /// Card rejected the written data due to a CRC error.
+    WriteCrcError,
+    /// Card sent a data response token not defined by the spec.
+    UnknownDataResponse(u8),
+    /// Card reported a general or unknown error.
+    CardError,
+    /// Card internal controller error.
+    CardControllerError,
+    /// Card internal ECC failed to correct the data.
+    CardEccFailed,
+    /// Address is out of the card range.
+    OutOfRange,
     /// Can't perform this operation with the card in this state.
     BadState,
     /// Couldn't find the card.
     CardNotFound,
+    /// Card doesn't support the supply voltage.
+    UnsupportedVoltage,
+    /// Card echoed back a wrong check pattern of SEND_IF_COND.
+    CheckPatternMismatch,
+    /// Card was replaced by another one while the driver was initialized.
+    CardChanged,
+}
+
+/// Error of a read or write, with count of blocks transferred before the failure.
+///
+/// `E` - hardware error type.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferError<E> {
+    /// Count of blocks transferred before the failure.
+    pub blocks: usize,
+    /// Error.
+    pub error: DiskioError<E>,
+}
+
+impl<E> TransferError<E> {
+    /// Creates a new [`TransferError<E>`].
+    pub fn new(blocks: usize, error: DiskioError<E>) -> Self {
+        TransferError { blocks, error }
+    }
+}
+
+impl<E> From<DiskioError<E>> for TransferError<E> {
+    fn from(error: DiskioError<E>) -> Self {
+        TransferError::new(0, error)
+    }
 }
 
 /// Card type.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
 pub enum CardType {
+    /// Standard capacity, version 1.x.
     SD1,
+    /// Standard capacity, version 2.0 or later.
     SD2,
+    /// High capacity, up to 32 GB.
     SDHC,
+    /// Extended capacity, up to 2 TB.
+    SDXC,
+    /// Ultra capacity, up to 128 TB, addressed with ADDRESS_EXTENSION.
+    SDUC,
+}
+
+impl CardType {
+    /// Min capacity of SDXC in 512-byte blocks, C_SIZE of 0xFFFF.
+    const SDXC_MIN_BLOCKS: u64 = 0x1_0000 * 1024;
+    /// Max block address of SDUC, 6 bits of ADDRESS_EXTENSION and 32 bits of the argument.
+    const SDUC_MAX_LBA: Lba = (1 << 38) - 1;
+
+    /// Argument of ACMD41 for the card type, HCS and HO2T if high capacity is supported.
+    pub(crate) fn op_cond_arg(&self) -> u32 {
+        match self {
+            CardType::SD1 => 0x0000_0000,
+            CardType::SD2 | CardType::SDHC | CardType::SDXC | CardType::SDUC => 0x4800_0000,
+        }
+    }
+
+    /// Card type of a ready card by OCR.
+    pub(crate) fn with_ocr(self, ocr: &Ocr) -> Self {
+        match self {
+            CardType::SD2 if ocr.card_capacity_over_2tb() => CardType::SDUC,
+            CardType::SD2 if ocr.card_capacity_status() => CardType::SDHC,
+            card_type => card_type,
+        }
+    }
+
+    /// Card type by CSD capacity, high capacity cards above 32 GB are SDXC.
+    pub(crate) fn with_csd(self, csd: &Csd) -> Self {
+        match self {
+            CardType::SDHC if csd.card_capacity_blocks() >= Self::SDXC_MIN_BLOCKS => CardType::SDXC,
+            card_type => card_type,
+        }
+    }
+
+    /// Convert lba to the card data address, `None` if the card can't address it.
+    ///
+    /// Bits above 32 of SDUC address are sent with ADDRESS_EXTENSION.
+    pub(crate) fn convert_lba(&self, lba: Lba) -> Option<u64> {
+        let address = match self {
+            CardType::SD1 | CardType::SD2 => lba.checked_mul(BLOCK_SIZE_U64)?,
+            CardType::SDHC | CardType::SDXC | CardType::SDUC => lba,
+        };
+        let max_address = match self {
+            CardType::SDUC => Self::SDUC_MAX_LBA,
+            _ => Lba::from(u32::MAX),
+        };
+
+        (address <= max_address).then_some(address)
+    }
 }
 
 /// Error type alias.
@@ -74,512 +201,270 @@ type ErrorFor<T> = <T as DiskioDevice>::HardwareError;
 
 /// SD Card SPI driver.
 ///
-/// `Spi` - SPI.
-/// `Cs` - Chip select output switch.
+/// `T` - SPI transport.
+/// `Delay` - delay, used to wait while the card is busy.
 /// `Config` - Config implementation of driver config trait.
-pub struct SdMmcSpi<Spi: Transfer<u8>, Cs: OutputSwitch, Config: SdMmcSpiConfig> {
-    spi: RefCell<Spi>,
-    cs: RefCell<Cs>,
-    status: Status,
-    card_type: CardType,
-    csd: Csd,
+pub struct SdMmcSpi<T: Transport, Delay: DelayNs, Config: SdMmcSpiConfig> {
+    transport: RefCell<T>,
+    delay: RefCell<Delay>,
+    state: CardState,
     config: PhantomData<Config>,
 }
 
-impl<Spi: Transfer<u8>, Cs: OutputSwitch, Config: SdMmcSpiConfig> SdMmcSpi<Spi, Cs, Config>
+impl<Spi, Cs: OutputSwitch, Delay: DelayNs, Config: SdMmcSpiConfig>
+    SdMmcSpi<SpiTransport<Spi, Cs>, Delay, Config>
 where
-    Spi::Error: core::fmt::Debug,
+    Spi: Transfer<u8> + Write<u8, Error = <Spi as Transfer<u8>>::Error>,
+    <Spi as Transfer<u8>>::Error: core::fmt::Debug,
     Cs::Error: core::fmt::Debug,
 {
-    /// Init sequence value.
-    const INIT_SET_VALUE: u8 = 0xFF;
-    /// Init sequence size.
-    const INIT_SET_SIZE: usize = 10;
-    /// Receive transfer token.
-    const RECEIVE_TRANSFER_TOKEN: u8 = 0xFF;
-
-    /// Creates a new [`SdMmcSpi<Spi, Cs, Config>`].
+    /// Creates a new [`SdMmcSpi`] on embedded-hal 0.2 SPI.
     ///
     /// `spi` - SPI instance.
     /// `cs` - chip select output switch.
-    pub fn new(spi: Spi, cs: Cs) -> Self {
-        SdMmcSpi {
-            spi: RefCell::new(spi),
-            cs: RefCell::new(cs),
-            status: StatusFlag::NotInitialized.into(),
-            card_type: CardType::SD1,
-            csd: Csd::V1(CsdV1(0)),
-            config: PhantomData::<Config>,
-        }
-    }
-
-    /// Validate buffer for read/write.
-    fn validate_buffer_len(buf_len: usize) -> Result<(), DiskioError<ErrorFor<Self>>> {
-        if buf_len == 0 || buf_len % BLOCK_SIZE != 0 {
-            error!(
-                "SD invalid buffer, length: {}, block size: {}",
-                buf_len, BLOCK_SIZE
-            );
-            Err(DiskioError::InvalidArgument)
-        } else {
-            Ok(())
-        }
-    }
-
-    /// Validate initialzed.
-    fn validate_initialized(&self) -> Result<(), DiskioError<ErrorFor<Self>>> {
-        if self.status.contains(StatusFlag::NotInitialized) {
-            Err(DiskioError::NotInitialized)
-        } else {
-            Ok(())
-        }
+    /// `delay` - delay instance.
+    pub fn new(spi: Spi, cs: Cs, delay: Delay) -> Self {
+        Self::with_transport(SpiTransport::new(spi, cs), delay)
     }
+}
 
-    /// Get count of blocks in buffer.
-    fn get_block_count(buf_len: usize) -> usize {
-        buf_len / BLOCK_SIZE
+impl<Spi: SpiDevice, Delay: DelayNs, Config: SdMmcSpiConfig>
+    SdMmcSpi<SpiDeviceTransport<Spi>, Delay, Config>
+{
+    /// Creates a new [`SdMmcSpi`] on embedded-hal 1.0 SPI device.
+    ///
+    /// `spi` - SPI device instance, chip select is managed by the device.
+    /// `delay` - delay instance.
+    pub fn new_device(spi: Spi, delay: Delay) -> Self {
+        Self::with_transport(SpiDeviceTransport::new(spi), delay)
     }
+}
 
-    /// Delay.
-    fn delay() {
-        for i in 0..Config::DELAY_DUMMY_CYCLES {
-            unsafe { core::ptr::read_volatile(&i) };
-        }
+impl<Spi: SpiBus, Cs: OutputPin, Delay: DelayNs, Config: SdMmcSpiConfig>
+    SdMmcSpi<SpiBusTransport<Spi, Cs>, Delay, Config>
+{
+    /// Creates a new [`SdMmcSpi`] on embedded-hal 1.0 SPI bus.
+    ///
+    /// `spi` - SPI bus instance.
+    /// `cs` - chip select output pin.
+    /// `delay` - delay instance.
+    pub fn new_bus(spi: Spi, cs: Cs, delay: Delay) -> Self {
+        Self::with_transport(SpiBusTransport::new(spi, cs), delay)
     }
+}
 
-    /// Convert lba.
-    fn convert_lba(&self, lba: Lba) -> u32 {
-        match self.card_type {
-            CardType::SD1 | CardType::SD2 => (lba as usize * BLOCK_SIZE) as u32,
-            CardType::SDHC => lba as u32,
+impl<T: Transport, Delay: DelayNs, Config: SdMmcSpiConfig> SdMmcSpi<T, Delay, Config> {
+    /// Creates a new [`SdMmcSpi<T, Delay, Config>`].
+    ///
+    /// `transport` - SPI transport.
+    /// `delay` - delay instance.
+    pub fn with_transport(transport: T, delay: Delay) -> Self {
+        SdMmcSpi {
+            transport: RefCell::new(transport),
+            delay: RefCell::new(delay),
+            state: CardState::new::<Config>(),
+            config: PhantomData::<Config>,
         }
     }
 
-    /// Activate chip select.
-    fn select(&self) -> Result<(), ErrorFor<Self>> {
-        self.cs.borrow_mut().on().map_err(Error::SelectError)
+    /// Card type, available after initialization.
+    pub fn card_type(&self) -> Option<CardType> {
+        self.state.info().map(|info| info.card_type)
     }
 
-    /// Deactivate chip select.
-    fn unselect(&self) -> Result<(), ErrorFor<Self>> {
-        self.cs.borrow_mut().off().map_err(Error::SelectError)
+    /// Card Specific Data, available after initialization.
+    pub fn csd(&self) -> Option<&Csd> {
+        self.state.info().map(|info| &info.csd)
     }
 
-    /// CS scope.
-    fn cs_scope<F>(&self, f: F) -> Result<(), ErrorFor<Self>>
-    where
-        F: FnOnce(&Self) -> Result<(), ErrorFor<Self>>,
-    {
-        self.select()?;
-        let result = f(self);
-        self.unselect()?;
-
-        result
+    /// Card capacity in bytes, available after initialization.
+    pub fn card_capacity(&self) -> Option<Size> {
+        self.csd().map(|csd| csd.card_capacity())
     }
 
-    /// CS scope mut.
-    fn cs_scope_mut<F>(&mut self, f: F) -> Result<(), ErrorFor<Self>>
-    where
-        F: FnOnce(&mut Self) -> Result<(), ErrorFor<Self>>,
-    {
-        self.select()?;
-        let result = f(self);
-        self.unselect()?;
-
-        result
+    /// Card capacity in 512-byte blocks, available after initialization.
+    pub fn card_capacity_blocks(&self) -> Option<u64> {
+        self.csd().map(|csd| csd.card_capacity_blocks())
     }
 
-    /// Send one byte and receive one byte.
-    fn transfer(&self, data: u8) -> Result<u8, ErrorFor<Self>> {
-        self.spi
-            .borrow_mut()
-            .transfer(&mut [data])
-            .map(|b| b[0])
-            .map_err(Error::Transport)
+    /// Card Identification, available after initialization.
+    pub fn cid(&self) -> Option<&Cid> {
+        self.state.info().map(|info| &info.cid)
     }
 
-    /// Receive a byte from the SD card by clocking in an 0xFF byte.
-    fn receive(&self) -> Result<u8, ErrorFor<Self>> {
-        self.transfer(Self::RECEIVE_TRANSFER_TOKEN)
+    /// Operation Conditions Register, available after initialization.
+    pub fn ocr(&self) -> Option<&Ocr> {
+        self.state.info().map(|info| &info.ocr)
     }
 
-    /// Send a byte to the SD card.
-    fn send(&self, data: u8) -> Result<(), ErrorFor<Self>> {
-        self.transfer(data).map(|_| ())
+    /// SD Card Configuration, available after initialization if the card supports SEND_SCR.
+    pub fn scr(&self) -> Option<&Scr> {
+        self.state.info().and_then(|info| info.scr.as_ref())
     }
 
-    /// Receive a slice from the SD card.
-    fn receive_slice(&self, data: &mut [u8]) -> Result<(), ErrorFor<Self>> {
-        for byte in data.iter_mut() {
-            *byte = self.receive()?;
-        }
-
-        Ok(())
+    /// Switch function status, available after initialization if the card supports SWITCH_FUNC.
+    pub fn switch_status(&self) -> Option<&SwitchStatus> {
+        self.state
+            .info()
+            .and_then(|info| info.switch_status.as_ref())
     }
 
-    /// Send a slice to the SD card.
-    fn send_slice(&self, data: &[u8]) -> Result<(), ErrorFor<Self>> {
-        for byte in data.iter() {
-            self.send(*byte)?;
-        }
-
-        Ok(())
+    /// SD Status, available after initialization if the card supports SD_STATUS.
+    pub fn sd_status(&self) -> Option<&SdStatus> {
+        self.state.info().and_then(|info| info.sd_status.as_ref())
     }
 
-    /// Skip byte.
-    fn skip_byte(&self) -> Result<(), ErrorFor<Self>> {
-        self.receive().map(|_| ())
+    /// Erase blocks from `start_lba` to `end_lba` inclusive.
+    ///
+    /// Erase timeout is derived from [`SdStatus`] if the card reports it, otherwise
+    /// from [`SdMmcSpiConfig::ERASE_TIMEOUT_PER_SECTOR_MS`] and the count of erase sectors,
+    /// at least [`SdMmcSpiConfig::ERASE_TIMEOUT_MS`].
+    ///
+    /// If the card can't erase single blocks, the range must be aligned to
+    /// [`Csd::erase_unit_blocks`]. Erased blocks read as [`Scr::erased_byte`],
+    /// or 0x00 or 0xFF depending on the card if SCR is not available.
+    pub fn erase(&self, start_lba: Lba, end_lba: Lba) -> Result<(), DiskioError<ErrorFor<Self>>> {
+        block_on(self.protocol().erase(start_lba, end_lba))
     }
 
-    /// Wait for token.
-    fn wait_for_token<F: Fn(u8) -> bool>(
+    /// Read data blocks from the card, on failure reports count of completely read blocks.
+    ///
+    /// Failed transfers are retried according to [`SdMmcSpiConfig`].
+    ///
+    /// `buf` - buffer, multiple of block size.
+    /// `lba` - address of the first block.
+    pub fn read_blocks(
         &self,
-        token_validator: F,
-        error: ErrorFor<Self>,
-    ) -> Result<u8, ErrorFor<Self>> {
-        for _ in 0..Config::CMD_MAX_ATTEMPTS {
-            let token = self.receive()?;
-
-            if token_validator(token) {
-                return Ok(token);
-            }
-
-            Self::delay();
-        }
-
-        Err(error)
+        buf: &mut [u8],
+        lba: Lba,
+    ) -> Result<(), TransferError<ErrorFor<Self>>> {
+        block_on(self.protocol().read_blocks(buf, lba))
     }
 
-    /// Wait available state of card.
-    fn wait_available_state(&self) -> Result<(), ErrorFor<Self>> {
-        self.wait_for_token(
-            |token| token == tokens::AVAILABLE,
-            Error::TimeoutWaitAvailable,
-        )
-        .map(|_| ())
+    /// Write data blocks to the card, on failure reports count of written blocks.
+    ///
+    /// The count of a failed multi-block write is reported by the card if it is available,
+    /// otherwise it is the count of blocks accepted by the card.
+    /// Failed transfers are retried according to [`SdMmcSpiConfig`].
+    ///
+    /// `buf` - buffer, multiple of block size.
+    /// `lba` - address of the first block.
+    pub fn write_blocks(&self, buf: &[u8], lba: Lba) -> Result<(), TransferError<ErrorFor<Self>>> {
+        block_on(self.protocol().write_blocks(buf, lba))
     }
 
-    /// Send command implementation.
-    fn send_command_impl(&self, cmd: u8, arg: u32) -> Result<R1Response, ErrorFor<Self>> {
-        self.wait_available_state()?;
-
-        let mut buf = [
-            cmd,
-            (arg >> 24) as u8,
-            (arg >> 16) as u8,
-            (arg >> 8) as u8,
-            arg as u8,
-            0,
-        ];
-        let crc_index = buf.len() - 1;
-
-        buf[crc_index] = (crc7(&buf[..crc_index]) << 1) | 0x01;
-
-        self.send_slice(&buf)?;
-
-        if cmd == commands::CMD12 {
-            self.skip_byte()?;
-        }
-
-        for _ in 0..Config::READ_R1_ATTEMPTS {
-            let r1 = R1Response(self.receive()?);
-
-            if r1.is_valid() {
-                return Ok(r1);
-            }
-        }
-
-        Err(Error::TimeoutCommand(cmd))
+    /// Mutable access to transport.
+    pub fn transport_mut(&mut self) -> &mut T {
+        self.transport.get_mut()
     }
 
-    /// Send command.
-    fn send_command(&self, cmd: u8, arg: u32) -> Result<R1Response, ErrorFor<Self>> {
-        if (cmd & commands::ACMD_FLAG) != 0 {
-            self.send_command_impl(commands::CMD55, 0x0000_0000)?;
-        }
-
-        self.send_command_impl(cmd & !commands::ACMD_FLAG, arg)
+    /// Releases transport and delay.
+    pub fn release(self) -> (T, Delay) {
+        (self.transport.into_inner(), self.delay.into_inner())
     }
 
-    /// Read data.
-    fn read_data(&self, data: &mut [u8]) -> Result<(), ErrorFor<Self>> {
-        if self.wait_for_token(|token| token != tokens::AVAILABLE, Error::TimeoutReadBuffer)?
-            != tokens::DATA_START_BLOCK
-        {
-            return Err(Error::ReadError);
-        }
-
-        self.receive_slice(data)?;
-
-        let card_crc = (u16::from(self.receive()?) << 8) | u16::from(self.receive()?);
-        let host_crc = crc16(data);
-
-        if card_crc != host_crc {
-            return Err(Error::CrcError(card_crc, host_crc));
-        }
+    /// Protocol on the transport.
+    fn protocol(&self) -> Protocol<'_, BlockingBus<'_, T, Delay>, Config> {
+        let bus = BlockingBus {
+            transport: self.transport.borrow_mut(),
+            delay: self.delay.borrow_mut(),
+        };
 
-        Ok(())
+        Protocol::new(bus, &self.state)
     }
+}
 
-    /// Write data.
-    fn write_data(&self, token: u8, data: &[u8]) -> Result<(), ErrorFor<Self>> {
-        let host_crc = crc16(data);
-
-        self.send(token)?;
-        self.send_slice(data)?;
-        self.send((host_crc >> 8) as u8)?;
-        self.send(host_crc as u8)?;
+impl<T: Transport, Delay: DelayNs, Config: SdMmcSpiConfig> DiskioDevice
+    for SdMmcSpi<T, Delay, Config>
+{
+    type HardwareError = Error<T::Error, T::SelectError>;
 
-        if (self.receive()? & tokens::DATA_RES_MASK) != tokens::DATA_RES_ACCEPTED {
-            Err(Error::WriteError)
-        } else {
-            Ok(())
-        }
+    fn status(&self) -> Status {
+        self.state.status.get()
     }
 
-    /// Enter SD to SPI mode.
-    fn enter_spi_mode(&self) -> Result<(), ErrorFor<Self>> {
-        for i in 0..Config::ENTER_SPI_MODE_ATTEMPTS {
-            info!("Enter to SPI mode for SD, attempt: {}", i + 1);
+    fn reset(&mut self) {
+        self.state.reset();
+    }
 
-            match self.send_command(commands::CMD0, 0x0000_0000) {
-                Ok(R1Response::IN_IDLE_STATE) => return Ok(()),
-                Ok(r) => warn!(
-                    "Wrong response from CMD{}: 0b{:02X}",
-                    commands::CMD0 - commands::CMD_BASE,
-                    r.0
-                ),
-                Err(Error::TimeoutCommand(commands::CMD0)) => {}
-                Err(err) => return Err(err),
-            }
+    fn initialize(&mut self) -> Result<(), DiskioError<Self::HardwareError>> {
+        self.state.start_init::<Config, _>()?;
 
-            Self::delay();
-        }
+        let result = block_on(self.protocol().init());
 
-        Err(Error::TimeoutCommand(commands::CMD0))
+        self.state.finish_init::<Config, _, _>(result)
     }
 
-    /// Enable CRC.
-    fn enable_crc(&self) -> Result<(), ErrorFor<Self>> {
-        info!("Enabling CRC for SD");
-
-        if self.send_command(commands::CMD59, 0x0000_0001)? != R1Response::IN_IDLE_STATE {
-            Err(Error::CantEnableCRC)
-        } else {
-            Ok(())
-        }
+    fn read(&self, buf: &mut [u8], lba: Lba) -> Result<(), DiskioError<Self::HardwareError>> {
+        self.read_blocks(buf, lba).map_err(|err| err.error)
     }
 
-    /// Verify SD Memory Card interface operating condition.
-    fn send_if_cond(&self) -> Result<CardType, ErrorFor<Self>> {
-        info!("Verifing SD Memory Card interface operating condition");
-
-        for _ in 0..Config::CMD_MAX_ATTEMPTS {
-            if self.send_command(commands::CMD8, 0x0000_01AA)? == R1Response::IN_IDLE_AND_ILLEGAL {
-                return Ok(CardType::SD1);
-            }
-
-            self.skip_byte()?;
-            self.skip_byte()?;
-            self.skip_byte()?;
-
-            if self.receive()? == tokens::CMD8_STATUS {
-                return Ok(CardType::SD2);
-            }
-        }
-
-        Err(Error::TimeoutCommand(commands::CMD8))
+    fn write(&self, buf: &[u8], lba: Lba) -> Result<(), DiskioError<Self::HardwareError>> {
+        self.write_blocks(buf, lba).map_err(|err| err.error)
     }
 
-    /// Sends host capacity support information and activates.
-    fn send_op_comd(&self, arg: u32) -> Result<(), ErrorFor<Self>> {
-        info!("Sending host capacity support information and activates");
-
-        for _ in 0..Config::CMD_MAX_ATTEMPTS {
-            if self.send_command(commands::ACMD41, arg)? == R1Response::READY_STATE {
-                return Ok(());
-            }
-        }
-
-        Err(Error::TimeoutCommand(commands::ACMD41))
+    fn ioctl(&self, cmd: IoctlCmd) -> Result<(), DiskioError<Self::HardwareError>> {
+        block_on(self.protocol().ioctl(cmd))
     }
+}
 
-    /// Check SD type.
-    fn check_type(&self) -> Result<CardType, ErrorFor<Self>> {
-        info!("Checking SD type");
-
-        let mut card_type = self.send_if_cond()?;
-
-        let arg = match card_type {
-            CardType::SD1 => 0x0000_0000,
-            CardType::SD2 | CardType::SDHC => 0x4000_0000,
-        };
-
-        self.send_op_comd(arg)?;
-
-        if card_type == CardType::SD2 {
-            if self.send_command(commands::CMD58, 0x0000_0000)? != R1Response::READY_STATE {
-                return Err(Error::ErrorCommand(commands::CMD58));
-            }
-            if (self.receive()? & tokens::CMD58_OCR) == tokens::CMD58_OCR {
-                card_type = CardType::SDHC;
-            }
+/// Bus of [`SdMmcSpi`], completes every operation on the first poll.
+struct BlockingBus<'a, T, Delay> {
+    transport: RefMut<'a, T>,
+    delay: RefMut<'a, Delay>,
+}
 
-            self.skip_byte()?;
-            self.skip_byte()?;
-            self.skip_byte()?;
-        }
+impl<T: Transport, Delay: DelayNs> Bus for BlockingBus<'_, T, Delay> {
+    type Error = T::Error;
+    type SelectError = T::SelectError;
 
-        Ok(card_type)
+    async fn select(&mut self) -> Result<(), Self::SelectError> {
+        self.transport.select()
     }
 
-    /// Read CSD.
-    fn read_csd(&self) -> Result<Csd, ErrorFor<Self>> {
-        let mut csd_data: CsdData = Default::default();
-
-        if self.send_command(commands::CMD9, 0x0000_0000)? != R1Response::READY_STATE {
-            return Err(Error::RegisterReadError);
-        }
-
-        self.read_data(&mut csd_data)?;
-
-        Ok(match self.card_type {
-            CardType::SD1 => Csd::V1(CsdV1::from(csd_data)),
-            CardType::SD2 | CardType::SDHC => Csd::V2(CsdV2::from(csd_data)),
-        })
+    async fn unselect(&mut self) -> Result<(), Self::SelectError> {
+        self.transport.unselect()
     }
 
-    /// Initialize SD.
-    fn init(&mut self) -> Result<(), ErrorFor<Self>> {
-        info!("SD initialize started");
-
-        self.unselect()?;
-
-        for _ in 0..Self::INIT_SET_SIZE {
-            self.send(Self::INIT_SET_VALUE)?;
-        }
-
-        let mut result = self.cs_scope_mut(|s| {
-            s.enter_spi_mode()?;
-            s.enable_crc()?;
-
-            s.card_type = s.check_type()?;
-            s.csd = s.read_csd()?;
-
-            Ok(())
-        });
-
-        self.status = match &result {
-            Ok(_) => {
-                info!(
-                    "SD successfully initialized, version: {}, capacity: {}",
-                    &self.card_type,
-                    defmt::Debug2Format(&self.csd.card_capacity())
-                );
-                Status::default()
-            }
-            Err(err) => {
-                error!("Failed to initialize SD: {}", defmt::Debug2Format(err));
-                result = Err(Error::CardNotFound);
-                StatusFlag::ErrorOccured | StatusFlag::NotInitialized
-            }
-        };
-
-        result
+    async fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.transport.transfer(data)
     }
-}
 
-impl<Spi: Transfer<u8>, Cs: OutputSwitch, Config: SdMmcSpiConfig> DiskioDevice
-    for SdMmcSpi<Spi, Cs, Config>
-where
-    Spi::Error: core::fmt::Debug,
-    Cs::Error: core::fmt::Debug,
-{
-    type HardwareError = Error<Spi::Error, Cs::Error>;
-
-    fn status(&self) -> Status {
-        self.status
+    async fn command(&mut self, frame: &[u8], response: &mut [u8]) -> Result<(), Self::Error> {
+        self.transport.command(frame, response)
     }
 
-    fn reset(&mut self) {
-        info!("SD reset invoked");
-        self.status = StatusFlag::NotInitialized.into();
+    async fn receive_data(&mut self, data: &mut [u8], crc: &mut [u8]) -> Result<(), Self::Error> {
+        self.transport.receive_data(data, crc)
     }
 
-    fn initialize(&mut self) -> Result<(), DiskioError<Self::HardwareError>> {
-        if !self.status.contains(StatusFlag::NotInitialized) {
-            warn!("SD already is initialized");
-            return Err(DiskioError::AlreadyInitialized);
-        }
-
-        self.init().map_err(DiskioError::Hardware)
+    async fn send_data(
+        &mut self,
+        token: &[u8],
+        data: &[u8],
+        crc: &[u8],
+        response: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.transport.send_data(token, data, crc, response)
     }
 
-    fn read(&self, buf: &mut [u8], lba: Lba) -> Result<(), DiskioError<Self::HardwareError>> {
-        Self::validate_buffer_len(buf.len())?;
-        self.validate_initialized()?;
-
-        let block_count = Self::get_block_count(buf.len());
-        let lba = self.convert_lba(lba);
-
-        self.cs_scope(|s| {
-            if block_count == 1 {
-                s.send_command(commands::CMD17, lba)?;
-                s.read_data(buf)?;
-            } else {
-                s.send_command(commands::CMD18, lba)?;
-                for chunk in buf.chunks_mut(BLOCK_SIZE) {
-                    s.read_data(chunk)?;
-                }
-                s.send_command(commands::CMD12, 0x0000_0000)?;
-            }
-
-            Ok(())
-        })
-        .map_err(DiskioError::Hardware)
+    async fn set_clock(&mut self, hz: u32) -> Result<(), Self::Error> {
+        self.transport.set_clock(hz)
     }
 
-    fn write(&self, buf: &[u8], lba: Lba) -> Result<(), DiskioError<Self::HardwareError>> {
-        Self::validate_buffer_len(buf.len())?;
-        self.validate_initialized()?;
-
-        let block_count = Self::get_block_count(buf.len());
-        let lba = self.convert_lba(lba);
-
-        self.cs_scope(|s| {
-            if block_count == 1 {
-                s.send_command(commands::CMD24, lba)?;
-                s.write_data(tokens::DATA_START_BLOCK, buf)?;
-                s.wait_available_state()?;
-                if s.send_command(commands::CMD13, 0x0000_0000)? != R1Response::READY_STATE {
-                    return Err(Error::WriteError);
-                }
-                if s.receive()? != R1Response::READY_STATE.0 {
-                    return Err(Error::WriteError);
-                }
-            } else {
-                s.send_command(commands::CMD25, lba)?;
-                for block in buf.chunks(BLOCK_SIZE) {
-                    s.wait_available_state()?;
-                    self.write_data(tokens::WRITE_MULTIPLE, block)?;
-                }
-                s.wait_available_state()?;
-                s.send(tokens::STOP_TRAN)?;
-            }
-
-            Ok(())
-        })
-        .map_err(DiskioError::Hardware)
+    async fn delay_us(&mut self, us: u32) {
+        self.delay.delay_us(us);
     }
+}
 
-    fn ioctl(&self, cmd: IoctlCmd) -> Result<(), DiskioError<Self::HardwareError>> {
-        match cmd {
-            IoctlCmd::CtrlSync => self.wait_available_state().map_err(DiskioError::Hardware),
-            IoctlCmd::GetBlockSize(block_size) => {
-                *block_size = BLOCK_SIZE;
-                Ok(())
-            }
-            _ => Err(DiskioError::NotSupported),
+/// Run future of [`BlockingBus`] to completion, it is never pending.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
         }
     }
 }
@@ -0,0 +1,1140 @@
+use crate::{
+    card::{self, CardInfo, PRE_ERASE_COUNT_MASK, SWITCH_CHECK_HIGH_SPEED, SWITCH_SET_HIGH_SPEED},
+    command,
+    consts::{commands, tokens, BLOCK_SIZE},
+    crc::crc16,
+    csd::{CidData, CsdData, OcrData, ScrData},
+    response::{DataErrorToken, DataResponse},
+    CapacityProvider, CardType, Cid, Csd, DiskioError, Error, IoctlCmd, Lba, Ocr, Scr,
+    SdMmcSpiConfig, SdStatus, Status, StatusFlag, SwitchStatus, TransferError, R1, R2, R7,
+};
+
+use core::{cell::Cell, fmt::Debug, marker::PhantomData};
+use defmt::{error, info, warn};
+
+/// Nanoseconds in a microsecond.
+const NANOS_PER_MICRO: u64 = 1_000;
+/// Nanoseconds in a millisecond.
+const NANOS_PER_MILLI: u64 = 1_000_000;
+/// Nanoseconds in a second.
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// SPI bus with chip select and delay, the protocol is run on.
+///
+/// Blocking buses complete every operation on the first poll.
+pub(crate) trait Bus {
+    /// Transport error type.
+    type Error: Debug;
+    /// Select error type.
+    type SelectError: Debug;
+
+    /// Activate chip select.
+    async fn select(&mut self) -> Result<(), Self::SelectError>;
+
+    /// Deactivate chip select.
+    async fn unselect(&mut self) -> Result<(), Self::SelectError>;
+
+    /// Send data and replace it in place by received data.
+    async fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Send command frame or token, then receive the response window by clocking out 0xFF bytes.
+    async fn command(&mut self, frame: &[u8], response: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Receive data block payload and CRC by clocking out 0xFF bytes.
+    async fn receive_data(&mut self, data: &mut [u8], crc: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Send data block token, payload and CRC, then receive the data response.
+    async fn send_data(
+        &mut self,
+        token: &[u8],
+        data: &[u8],
+        crc: &[u8],
+        response: &mut [u8],
+    ) -> Result<(), Self::Error>;
+
+    /// Change SPI clock frequency.
+    async fn set_clock(&mut self, hz: u32) -> Result<(), Self::Error>;
+
+    /// Delay, yields while the card is busy if the bus is async.
+    async fn delay_us(&mut self, us: u32);
+}
+
+/// Max size of a command response window.
+const RESPONSE_WINDOW_MAX: usize = 8;
+
+/// Response bytes received ahead with a command, consumed before the bus is clocked again.
+#[derive(Default)]
+struct Lookahead {
+    buf: [u8; RESPONSE_WINDOW_MAX],
+    start: usize,
+    end: usize,
+}
+
+impl Lookahead {
+    /// Replace bytes received ahead.
+    fn set(&mut self, bytes: &[u8]) {
+        self.buf[..bytes.len()].copy_from_slice(bytes);
+        self.start = 0;
+        self.end = bytes.len();
+    }
+
+    /// Discard bytes received ahead.
+    fn clear(&mut self) {
+        self.start = self.end;
+    }
+
+    /// Take the next byte received ahead.
+    fn pop(&mut self) -> Option<u8> {
+        let byte = self.buf[self.start..self.end].first().copied()?;
+        self.start += 1;
+
+        Some(byte)
+    }
+
+    /// Move bytes received ahead to the start of `data`, returns count of moved bytes.
+    fn take_into(&mut self, data: &mut [u8]) -> usize {
+        let len = (self.end - self.start).min(data.len());
+
+        data[..len].copy_from_slice(&self.buf[self.start..self.start + len]);
+        self.start += len;
+
+        len
+    }
+}
+
+/// Error type of the bus.
+pub(crate) type BusError<B> = Error<<B as Bus>::Error, <B as Bus>::SelectError>;
+
+/// State of the driver and the initialized card.
+pub(crate) struct CardState {
+    pub status: Cell<Status>,
+    pub info: CardInfo,
+    pub read_timeout_ms: u32,
+    pub write_timeout_ms: u32,
+}
+
+impl CardState {
+    /// Creates a new not initialized [`CardState`].
+    pub fn new<Config: SdMmcSpiConfig>() -> Self {
+        CardState {
+            status: Cell::new(StatusFlag::NotInitialized.into()),
+            info: CardInfo::default(),
+            read_timeout_ms: Config::READ_TIMEOUT_MS,
+            write_timeout_ms: Config::WRITE_TIMEOUT_MS,
+        }
+    }
+
+    /// Card info if initialized.
+    pub fn info(&self) -> Option<&CardInfo> {
+        if self.status.get().contains(StatusFlag::NotInitialized) {
+            None
+        } else {
+            Some(&self.info)
+        }
+    }
+
+    /// Validate initialzed.
+    pub fn validate_initialized<E>(&self) -> Result<(), DiskioError<E>> {
+        if self.status.get().contains(StatusFlag::NotInitialized) {
+            Err(DiskioError::NotInitialized)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reset state, the card must be initialized again.
+    pub fn reset(&self) {
+        info!("SD reset invoked");
+        self.status.set(StatusFlag::NotInitialized.into());
+    }
+
+    /// Prepare initialization, the card must not be initialized.
+    pub fn start_init<Config: SdMmcSpiConfig, E>(&mut self) -> Result<(), DiskioError<E>> {
+        if !self.status.get().contains(StatusFlag::NotInitialized) {
+            warn!("SD already is initialized");
+            return Err(DiskioError::AlreadyInitialized);
+        }
+
+        info!("SD initialize started");
+
+        self.read_timeout_ms = Config::READ_TIMEOUT_MS;
+        self.write_timeout_ms = Config::WRITE_TIMEOUT_MS;
+
+        Ok(())
+    }
+
+    /// Complete initialization with the result of the init sequence.
+    pub fn finish_init<Config: SdMmcSpiConfig, T: Debug, S: Debug>(
+        &mut self,
+        result: Result<CardInfo, Error<T, S>>,
+    ) -> Result<(), DiskioError<Error<T, S>>> {
+        let status = match &result {
+            Ok(info) => {
+                self.info = *info;
+                (self.read_timeout_ms, self.write_timeout_ms) = info.timeouts_ms::<Config>();
+
+                info!(
+                    "SD successfully initialized, version: {}, capacity: {}, manufacturer: 0x{:02X}, serial: 0x{:08X}",
+                    &info.card_type,
+                    defmt::Debug2Format(&info.csd.card_capacity()),
+                    info.cid.manufacturer_id(),
+                    info.cid.serial_number()
+                );
+                info!(
+                    "SD timeouts, read: {} ms, write: {} ms",
+                    self.read_timeout_ms, self.write_timeout_ms
+                );
+                Status::default()
+            }
+            Err(err) => {
+                error!("Failed to initialize SD: {}", defmt::Debug2Format(err));
+                StatusFlag::ErrorOccured | StatusFlag::NotInitialized
+            }
+        };
+        self.status.set(status);
+
+        result
+            .map(|_| ())
+            .map_err(|err| DiskioError::Hardware(card::init_error(err)))
+    }
+}
+
+/// SD SPI protocol, shared by the blocking and the async drivers.
+///
+/// `B` - bus.
+/// `Config` - Config implementation of driver config trait.
+pub(crate) struct Protocol<'a, B: Bus, Config: SdMmcSpiConfig> {
+    bus: B,
+    state: &'a CardState,
+    clock_hz: u32,
+    elapsed_ns: u64,
+    lookahead: Lookahead,
+    config: PhantomData<Config>,
+}
+
+impl<'a, B: Bus, Config: SdMmcSpiConfig> Protocol<'a, B, Config> {
+    /// Init sequence value.
+    const INIT_SET_VALUE: u8 = 0xFF;
+    /// Init sequence size.
+    const INIT_SET_SIZE: usize = 10;
+    /// Receive transfer token.
+    const RECEIVE_TRANSFER_TOKEN: u8 = 0xFF;
+    /// Delay between attempts of init commands, in microseconds.
+    const INIT_POLL_DELAY_US: u32 = 1000;
+    /// Bytes before R1 expected in the response window.
+    const NCR_BYTES: usize = 1;
+
+    /// Creates a new [`Protocol<B, Config>`].
+    ///
+    /// `bus` - bus.
+    /// `state` - state of the driver.
+    pub fn new(bus: B, state: &'a CardState) -> Self {
+        let clock_hz = state
+            .info()
+            .map_or(Config::INIT_CLOCK_HZ, |info| info.clock_hz::<Config>());
+
+        Protocol {
+            bus,
+            state,
+            clock_hz,
+            elapsed_ns: 0,
+            lookahead: Lookahead::default(),
+            config: PhantomData::<Config>,
+        }
+    }
+
+    /// Run init sequence, returns type, registers and switch status of the card.
+    pub async fn init(&mut self) -> Result<CardInfo, BusError<B>> {
+        self.unselect().await?;
+        self.set_clock(Config::INIT_CLOCK_HZ).await?;
+
+        for _ in 0..Self::INIT_SET_SIZE {
+            self.send(Self::INIT_SET_VALUE).await?;
+        }
+
+        self.cs_scope(async |s| {
+            s.enter_spi_mode().await?;
+            s.enable_crc().await?;
+
+            let (card_type, ocr) = s.check_type().await?;
+            let csd = s.read_csd().await?;
+            let card_type = card_type.with_csd(&csd);
+            let scr = s.read_scr().await?;
+            let switch_status = s.switch_high_speed(&csd, scr.as_ref()).await?;
+            let mut info = CardInfo {
+                card_type,
+                ocr,
+                csd,
+                cid: Cid(0),
+                scr,
+                switch_status,
+                sd_status: None,
+            };
+            s.set_clock(info.clock_hz::<Config>()).await?;
+            info.cid = s.read_cid().await?;
+            info.sd_status = s.read_sd_status().await?;
+
+            Ok(info)
+        })
+        .await
+    }
+
+    /// Read data blocks, on failure reports count of completely read blocks.
+    pub async fn read_blocks(
+        &mut self,
+        buf: &mut [u8],
+        lba: Lba,
+    ) -> Result<(), TransferError<BusError<B>>> {
+        card::validate_buffer_len(buf.len()).map_err(TransferError::from)?;
+        self.state
+            .validate_initialized()
+            .map_err(TransferError::from)?;
+        self.state
+            .info
+            .validate_address(lba, buf.len())
+            .map_err(TransferError::from)?;
+
+        self.with_retries(Self::get_block_count(buf.len()), async |s, done| {
+            s.read_blocks_once(&mut buf[done * BLOCK_SIZE..], lba + done as Lba)
+                .await
+        })
+        .await
+    }
+
+    /// Write data blocks, on failure reports count of written blocks.
+    pub async fn write_blocks(
+        &mut self,
+        buf: &[u8],
+        lba: Lba,
+    ) -> Result<(), TransferError<BusError<B>>> {
+        card::validate_buffer_len(buf.len()).map_err(TransferError::from)?;
+        self.state
+            .validate_initialized()
+            .map_err(TransferError::from)?;
+        self.state
+            .info
+            .validate_address(lba, buf.len())
+            .map_err(TransferError::from)?;
+
+        self.with_retries(Self::get_block_count(buf.len()), async |s, done| {
+            s.write_blocks_once(&buf[done * BLOCK_SIZE..], lba + done as Lba)
+                .await
+        })
+        .await
+    }
+
+    /// Erase blocks from `start_lba` to `end_lba` inclusive.
+    pub async fn erase(
+        &mut self,
+        start_lba: Lba,
+        end_lba: Lba,
+    ) -> Result<(), DiskioError<BusError<B>>> {
+        self.state.validate_initialized()?;
+        self.state.info.validate_erase_range(start_lba, end_lba)?;
+        self.state
+            .info
+            .validate_erase_alignment(start_lba, end_lba)?;
+
+        let timeout_ms = self
+            .state
+            .info
+            .erase_timeout_ms::<Config>(end_lba - start_lba + 1);
+
+        self.erase_blocks(start_lba, end_lba, timeout_ms)
+            .await
+            .map_err(DiskioError::Hardware)
+    }
+
+    /// Wait until the card finishes programming.
+    pub async fn sync(&mut self) -> Result<(), DiskioError<BusError<B>>> {
+        self.state.validate_initialized()?;
+
+        let timeout_ms = self.state.write_timeout_ms;
+
+        self.cs_scope(async |s| s.wait_available_state(timeout_ms).await)
+            .await
+            .map_err(DiskioError::Hardware)
+    }
+
+    /// Control the device.
+    pub async fn ioctl(&mut self, cmd: IoctlCmd<'_>) -> Result<(), DiskioError<BusError<B>>> {
+        self.state.validate_initialized()?;
+
+        let state = self.state;
+        let info = &state.info;
+
+        match cmd {
+            IoctlCmd::CtrlSync => self.sync().await,
+            IoctlCmd::GetSectorCount(sector_count) => {
+                *sector_count = info.csd.card_capacity_blocks();
+                Ok(())
+            }
+            IoctlCmd::GetSectorSize(sector_size) => {
+                *sector_size = BLOCK_SIZE;
+                Ok(())
+            }
+            IoctlCmd::GetBlockSize(block_size) => {
+                *block_size = info.block_size();
+                Ok(())
+            }
+            IoctlCmd::CtrlTrim(&(start, end)) => {
+                info.validate_erase_range(start, end)?;
+
+                match info.trim_range(start, end) {
+                    Some((start, end)) => self.erase(start, end).await,
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Get count of blocks in buffer.
+    fn get_block_count(buf_len: usize) -> usize {
+        buf_len / BLOCK_SIZE
+    }
+
+    /// Count of response bytes following R1 of the command.
+    fn response_tail_len(cmd: u8) -> usize {
+        match cmd {
+            commands::CMD8 | commands::CMD58 => 4,
+            commands::CMD13 => 1,
+            _ => 0,
+        }
+    }
+
+    /// Activate chip select.
+    async fn select(&mut self) -> Result<(), BusError<B>> {
+        self.lookahead.clear();
+        self.bus.select().await.map_err(Error::SelectError)
+    }
+
+    /// Deactivate chip select.
+    async fn unselect(&mut self) -> Result<(), BusError<B>> {
+        self.lookahead.clear();
+        self.bus.unselect().await.map_err(Error::SelectError)
+    }
+
+    /// CS scope.
+    async fn cs_scope<R>(
+        &mut self,
+        f: impl AsyncFnOnce(&mut Self) -> Result<R, BusError<B>>,
+    ) -> Result<R, BusError<B>> {
+        self.select().await?;
+        let result = f(self).await;
+        self.unselect().await?;
+
+        result
+    }
+
+    /// Send one byte and receive one byte.
+    async fn transfer(&mut self, data: u8) -> Result<u8, BusError<B>> {
+        let mut buf = [data];
+
+        self.count_bytes(buf.len());
+        self.bus
+            .transfer(&mut buf)
+            .await
+            .map_err(Error::Transport)?;
+
+        Ok(buf[0])
+    }
+
+    /// Receive a byte from the SD card, received ahead or by clocking in an 0xFF byte.
+    async fn receive(&mut self) -> Result<u8, BusError<B>> {
+        match self.lookahead.pop() {
+            Some(byte) => Ok(byte),
+            None => self.transfer(Self::RECEIVE_TRANSFER_TOKEN).await,
+        }
+    }
+
+    /// Send a byte to the SD card.
+    async fn send(&mut self, data: u8) -> Result<(), BusError<B>> {
+        self.transfer(data).await.map(|_| ())
+    }
+
+    /// Send command frame or token and receive the response window in one exchange.
+    async fn exchange(&mut self, frame: &[u8], response: &mut [u8]) -> Result<(), BusError<B>> {
+        self.lookahead.clear();
+        self.count_bytes(frame.len() + response.len());
+        self.bus
+            .command(frame, response)
+            .await
+            .map_err(Error::Transport)
+    }
+
+    /// Receive data block payload and CRC in one exchange, after the bytes received ahead.
+    async fn receive_data(
+        &mut self,
+        data: &mut [u8],
+        crc: &mut [u8; 2],
+    ) -> Result<(), BusError<B>> {
+        let data_ahead = self.lookahead.take_into(data);
+        let crc_ahead = self.lookahead.take_into(crc);
+        let (data, crc) = (&mut data[data_ahead..], &mut crc[crc_ahead..]);
+
+        self.count_bytes(data.len() + crc.len());
+        self.bus
+            .receive_data(data, crc)
+            .await
+            .map_err(Error::Transport)
+    }
+
+    /// Change SPI clock frequency.
+    async fn set_clock(&mut self, hz: u32) -> Result<(), BusError<B>> {
+        info!("SD SPI clock: {} Hz", hz);
+
+        self.bus.set_clock(hz).await.map_err(Error::Transport)?;
+        self.clock_hz = hz;
+
+        Ok(())
+    }
+
+    /// Delay, counted in the elapsed time.
+    async fn delay_us(&mut self, us: u32) {
+        self.bus.delay_us(us).await;
+        self.elapsed_ns += u64::from(us) * NANOS_PER_MICRO;
+    }
+
+    /// Count time of transferring `len` bytes at the current clock in the elapsed time.
+    fn count_bytes(&mut self, len: usize) {
+        let bits = len as u64 * u64::from(u8::BITS);
+
+        self.elapsed_ns += (bits * NANOS_PER_SECOND).div_ceil(u64::from(self.clock_hz.max(1)));
+    }
+
+    /// Elapsed time at which `timeout_ms` milliseconds from now run out.
+    fn deadline(&self, timeout_ms: u32) -> u64 {
+        self.elapsed_ns + u64::from(timeout_ms) * NANOS_PER_MILLI
+    }
+
+    /// Check if the elapsed time reached `deadline`.
+    fn is_expired(&self, deadline: u64) -> bool {
+        self.elapsed_ns >= deadline
+    }
+
+    /// Wait for token.
+    async fn wait_for_token<F: Fn(u8) -> bool>(
+        &mut self,
+        token_validator: F,
+        timeout_ms: u32,
+        error: BusError<B>,
+    ) -> Result<u8, BusError<B>> {
+        let deadline = self.deadline(timeout_ms);
+
+        loop {
+            let token = self.receive().await?;
+
+            if token_validator(token) {
+                return Ok(token);
+            }
+
+            if self.is_expired(deadline) {
+                return Err(error);
+            }
+
+            self.delay_us(Config::POLL_DELAY_US).await;
+        }
+    }
+
+    /// Wait available state of card, up to `timeout_ms` milliseconds.
+    async fn wait_available_state(&mut self, timeout_ms: u32) -> Result<(), BusError<B>> {
+        self.wait_for_token(
+            |token| token == tokens::AVAILABLE,
+            timeout_ms,
+            Error::TimeoutWaitAvailable,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Send command implementation.
+    async fn send_command_impl(&mut self, cmd: u8, arg: u32) -> Result<R1, BusError<B>> {
+        // Erase waits out its own busy period with the erase timeout, so a card still busy
+        // here is programming written data.
+        self.wait_available_state(self.state.write_timeout_ms)
+            .await?;
+
+        // The window covers the stuff byte of STOP_TRANSMISSION, NCR, R1 and the rest
+        // of the response, so a card answering in time is polled in the same exchange.
+        let stuff = usize::from(cmd == commands::CMD12);
+        let mut window = [0; RESPONSE_WINDOW_MAX];
+        let window = &mut window[..stuff + Self::NCR_BYTES + 1 + Self::response_tail_len(cmd)];
+
+        self.exchange(&command::frame(cmd, arg), window).await?;
+
+        let polled = &window[stuff..];
+        let response = polled
+            .iter()
+            .enumerate()
+            .find_map(|(i, &byte)| R1::from_bits(byte).map(|r1| (i, r1)));
+
+        if let Some((i, r1)) = response {
+            self.lookahead.set(&polled[i + 1..]);
+            return Ok(r1);
+        }
+
+        for _ in polled.len()..Config::READ_R1_ATTEMPTS {
+            if let Some(r1) = R1::from_bits(self.receive().await?) {
+                return Ok(r1);
+            }
+        }
+
+        Err(Error::TimeoutCommand(cmd))
+    }
+
+    /// Send command.
+    async fn send_command(&mut self, cmd: u8, arg: u32) -> Result<R1, BusError<B>> {
+        if (cmd & commands::ACMD_FLAG) != 0 {
+            self.send_command_impl(commands::CMD55, 0x0000_0000).await?;
+        }
+
+        self.send_command_impl(cmd & !commands::ACMD_FLAG, arg)
+            .await
+    }
+
+    /// Send command, expecting the card in ready state.
+    async fn send_command_ready(&mut self, cmd: u8, arg: u32) -> Result<(), BusError<B>> {
+        match self.send_command(cmd, arg).await? {
+            R1::READY_STATE => Ok(()),
+            r1 => Err(Error::ErrorCommand(cmd, r1.into())),
+        }
+    }
+
+    /// Send command with the data address of `lba`, preceded by ADDRESS_EXTENSION for SDUC.
+    async fn send_address_command(&mut self, cmd: u8, lba: Lba) -> Result<(), BusError<B>> {
+        let card_type = self.state.info.card_type;
+        let address = card_type.convert_lba(lba).ok_or(Error::OutOfRange)?;
+
+        if card_type == CardType::SDUC {
+            self.send_command_ready(commands::CMD22, (address >> 32) as u32)
+                .await?;
+        }
+
+        self.send_command_ready(cmd, address as u32).await
+    }
+
+    /// Read card status.
+    async fn send_status(&mut self) -> Result<R2, BusError<B>> {
+        let r1 = self.send_command(commands::CMD13, 0x0000_0000).await?;
+
+        Ok(R2::new(r1, self.receive().await?))
+    }
+
+    /// Read data.
+    async fn read_data(&mut self, data: &mut [u8], timeout_ms: u32) -> Result<(), BusError<B>> {
+        let token = self
+            .wait_for_token(
+                |token| token != tokens::AVAILABLE,
+                timeout_ms,
+                Error::TimeoutReadBuffer,
+            )
+            .await?;
+
+        if token != tokens::DATA_START_BLOCK {
+            return Err(DataErrorToken(token).error());
+        }
+
+        let mut crc = [0; 2];
+
+        self.receive_data(data, &mut crc).await?;
+
+        let card_crc = u16::from_be_bytes(crc);
+        let host_crc = crc16(data);
+
+        if card_crc != host_crc {
+            return Err(Error::CrcError(card_crc, host_crc));
+        }
+
+        Ok(())
+    }
+
+    /// Write data.
+    async fn write_data(&mut self, token: u8, data: &[u8]) -> Result<(), BusError<B>> {
+        let host_crc = crc16(data).to_be_bytes();
+        let mut response = [0; 1];
+
+        self.count_bytes(1 + data.len() + host_crc.len() + response.len());
+        self.bus
+            .send_data(&[token], data, &host_crc, &mut response)
+            .await
+            .map_err(Error::Transport)?;
+
+        DataResponse(response[0]).result()
+    }
+
+    /// Enter SD to SPI mode.
+    async fn enter_spi_mode(&mut self) -> Result<(), BusError<B>> {
+        for i in 0..Config::ENTER_SPI_MODE_ATTEMPTS {
+            info!("Enter to SPI mode for SD, attempt: {}", i + 1);
+
+            match self.send_command(commands::CMD0, 0x0000_0000).await {
+                Ok(R1::IN_IDLE_STATE) => return Ok(()),
+                Ok(r) => warn!(
+                    "Wrong response from CMD{}: 0b{:02X}",
+                    commands::CMD0 - commands::CMD_BASE,
+                    r.bits()
+                ),
+                Err(Error::TimeoutCommand(commands::CMD0)) => {}
+                Err(err) => return Err(err),
+            }
+
+            self.delay_us(Self::INIT_POLL_DELAY_US).await;
+        }
+
+        Err(Error::TimeoutCommand(commands::CMD0))
+    }
+
+    /// Enable CRC.
+    async fn enable_crc(&mut self) -> Result<(), BusError<B>> {
+        info!("Enabling CRC for SD");
+
+        if self.send_command(commands::CMD59, 0x0000_0001).await? != R1::IN_IDLE_STATE {
+            Err(Error::CantEnableCRC)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Verify SD Memory Card interface operating condition.
+    async fn send_if_cond(&mut self) -> Result<CardType, BusError<B>> {
+        info!("Verifing SD Memory Card interface operating condition");
+
+        let r1 = self.send_command(commands::CMD8, card::IF_COND_ARG).await?;
+
+        if let Some(card_type) = card::if_cond_card_type(r1)? {
+            return Ok(card_type);
+        }
+
+        let mut data = [0; 4];
+        for byte in &mut data {
+            *byte = self.receive().await?;
+        }
+
+        R7::new(r1, data).result().map(|_| CardType::SD2)
+    }
+
+    /// Sends host capacity support information and activates.
+    async fn send_op_comd(&mut self, arg: u32) -> Result<(), BusError<B>> {
+        info!("Sending host capacity support information and activates");
+
+        let deadline = self.deadline(Config::INIT_TIMEOUT_MS);
+
+        loop {
+            if self.send_command(commands::ACMD41, arg).await? == R1::READY_STATE {
+                return Ok(());
+            }
+
+            if self.is_expired(deadline) {
+                return Err(Error::TimeoutCommand(commands::ACMD41));
+            }
+
+            self.delay_us(Self::INIT_POLL_DELAY_US).await;
+        }
+    }
+
+    /// Read OCR, the card may be in idle state.
+    async fn read_ocr(&mut self) -> Result<Ocr, BusError<B>> {
+        let mut ocr_data: OcrData = Default::default();
+
+        card::check_ocr_r1(self.send_command(commands::CMD58, 0x0000_0000).await?)?;
+
+        for byte in &mut ocr_data {
+            *byte = self.receive().await?;
+        }
+
+        Ok(Ocr::from(ocr_data))
+    }
+
+    /// Check SD type and supply voltage, returns type and OCR of the ready card.
+    async fn check_type(&mut self) -> Result<(CardType, Ocr), BusError<B>> {
+        info!("Checking SD type");
+
+        let card_type = self.send_if_cond().await?;
+
+        card::check_supply_voltage::<Config, _, _>(&self.read_ocr().await?)?;
+
+        self.send_op_comd(card_type.op_cond_arg()).await?;
+
+        let ocr = self.read_ocr().await?;
+
+        Ok((card_type.with_ocr(&ocr), ocr))
+    }
+
+    /// Erase blocks between addresses, inclusive.
+    async fn erase_blocks(
+        &mut self,
+        start: Lba,
+        end: Lba,
+        timeout_ms: u32,
+    ) -> Result<(), BusError<B>> {
+        self.cs_scope(async |s| {
+            s.send_address_command(commands::CMD32, start).await?;
+            s.send_address_command(commands::CMD33, end).await?;
+            s.send_command_ready(commands::CMD38, 0x0000_0000).await?;
+
+            s.wait_for_token(
+                |token| token == tokens::AVAILABLE,
+                timeout_ms,
+                Error::TimeoutErase,
+            )
+            .await
+            .map(|_| ())
+        })
+        .await
+    }
+
+    /// Set count of blocks to be pre-erased before a multi-block write, if the card supports it.
+    async fn set_pre_erase_count(&mut self, block_count: usize) -> Result<(), BusError<B>> {
+        if !self.state.info.supports_pre_erase() {
+            return Ok(());
+        }
+
+        let count = (block_count as u32) & PRE_ERASE_COUNT_MASK;
+
+        if self.send_command(commands::ACMD23, count).await? != R1::READY_STATE {
+            warn!("SD doesn't support pre-erase, block count: {}", block_count);
+        }
+
+        Ok(())
+    }
+
+    /// Run transfer with retries.
+    ///
+    /// A failure after all blocks are transferred, e.g. of the stop command, isn't retried
+    /// from the failed block, there is nothing left to transfer.
+    ///
+    /// `block_count` - count of blocks of the transfer.
+    /// `transfer` - transfers blocks starting from the given block of the buffer,
+    /// on failure returns count of transferred blocks with the error.
+    async fn with_retries(
+        &mut self,
+        block_count: usize,
+        mut transfer: impl AsyncFnMut(&mut Self, usize) -> Result<(), (usize, BusError<B>)>,
+    ) -> Result<(), TransferError<BusError<B>>> {
+        let state = self.state;
+        let mut done = 0;
+        let mut retries = 0;
+        let mut recoveries = 0;
+
+        loop {
+            let (blocks, err) = match transfer(self, done).await {
+                Ok(()) => return Ok(()),
+                Err((blocks, err)) => (done + blocks, err),
+            };
+
+            if Config::is_card_lost(&err) {
+                state
+                    .status
+                    .set(state.status.get() | StatusFlag::ErrorOccured);
+
+                if recoveries == Config::RECOVERY_ATTEMPTS {
+                    return Err(TransferError::new(blocks, DiskioError::Hardware(err)));
+                }
+
+                // The card may lose data accepted before the failure,
+                // so the failed attempt is repeated from its first block.
+                recoveries += 1;
+                if let Err(err) = self.recover().await {
+                    return Err(TransferError::new(blocks, DiskioError::Hardware(err)));
+                }
+
+                continue;
+            }
+
+            if retries == Config::TRANSFER_RETRIES
+                || !Config::is_retryable(&err)
+                || (Config::RETRY_FROM_FAILED_BLOCK && blocks >= block_count)
+            {
+                return Err(TransferError::new(blocks, DiskioError::Hardware(err)));
+            }
+
+            retries += 1;
+            warn!(
+                "SD transfer failed after {} blocks, retry: {}, error: {}",
+                blocks,
+                retries,
+                defmt::Debug2Format(&err)
+            );
+
+            if Config::RETRY_FROM_FAILED_BLOCK {
+                done = blocks;
+            }
+        }
+    }
+
+    /// Read data blocks, single attempt.
+    async fn read_blocks_once(
+        &mut self,
+        buf: &mut [u8],
+        lba: Lba,
+    ) -> Result<(), (usize, BusError<B>)> {
+        let block_count = Self::get_block_count(buf.len());
+        let mut blocks = 0;
+
+        debug_assert_ne!(block_count, 0, "empty read");
+
+        self.cs_scope(async |s| {
+            let read_timeout_ms = s.state.read_timeout_ms;
+
+            if block_count == 1 {
+                s.send_address_command(commands::CMD17, lba).await?;
+                s.read_data(buf, read_timeout_ms).await?;
+                blocks = 1;
+            } else {
+                let predefined = s.state.info.supports_set_block_count();
+
+                if predefined {
+                    s.send_command_ready(commands::CMD23, block_count as u32)
+                        .await?;
+                }
+                s.send_address_command(commands::CMD18, lba).await?;
+
+                let mut result = Ok(());
+                for chunk in buf.chunks_mut(BLOCK_SIZE) {
+                    result = s.read_data(chunk, read_timeout_ms).await;
+                    if result.is_err() {
+                        break;
+                    }
+                    blocks += 1;
+                }
+                let stop = if predefined && result.is_ok() {
+                    Ok(R1::READY_STATE)
+                } else {
+                    s.send_command(commands::CMD12, 0x0000_0000).await
+                };
+
+                result?;
+                stop?;
+            }
+
+            Ok(())
+        })
+        .await
+        .map_err(|err| (blocks, err))
+    }
+
+    /// Write data blocks, single attempt.
+    async fn write_blocks_once(
+        &mut self,
+        buf: &[u8],
+        lba: Lba,
+    ) -> Result<(), (usize, BusError<B>)> {
+        let block_count = Self::get_block_count(buf.len());
+        let mut blocks = 0;
+
+        debug_assert_ne!(block_count, 0, "empty write");
+
+        self.cs_scope(async |s| {
+            let write_timeout_ms = s.state.write_timeout_ms;
+
+            if block_count == 1 {
+                s.send_address_command(commands::CMD24, lba).await?;
+                s.write_data(tokens::DATA_START_BLOCK, buf).await?;
+                s.wait_available_state(write_timeout_ms).await?;
+
+                let status = s.send_status().await?;
+                if !status.is_empty() {
+                    return Err(Error::ErrorCommand(commands::CMD13, status));
+                }
+                blocks = 1;
+            } else {
+                s.set_pre_erase_count(block_count).await?;
+                s.send_address_command(commands::CMD25, lba).await?;
+
+                let mut result = Ok(());
+                for block in buf.chunks(BLOCK_SIZE) {
+                    result = s.write_block_multiple(block).await;
+                    if result.is_err() {
+                        break;
+                    }
+                    blocks += 1;
+                }
+                let stop = s.stop_write_multiple().await;
+
+                if result.is_err() {
+                    // A count beyond the transfer can't be trusted, the accepted blocks are kept.
+                    if let Some(written) = s
+                        .read_written_blocks()
+                        .await
+                        .ok()
+                        .and_then(|written| usize::try_from(written).ok())
+                        .filter(|&written| written <= block_count)
+                    {
+                        blocks = written;
+                    }
+                }
+
+                result?;
+                stop?;
+            }
+
+            Ok(())
+        })
+        .await
+        .map_err(|err| (blocks, err))
+    }
+
+    /// Write one block of multi-block write.
+    async fn write_block_multiple(&mut self, block: &[u8]) -> Result<(), BusError<B>> {
+        self.wait_available_state(self.state.write_timeout_ms)
+            .await?;
+        self.write_data(tokens::WRITE_MULTIPLE, block).await
+    }
+
+    /// Stop multi-block write and wait until the card finishes programming.
+    async fn stop_write_multiple(&mut self) -> Result<(), BusError<B>> {
+        let write_timeout_ms = self.state.write_timeout_ms;
+
+        self.wait_available_state(write_timeout_ms).await?;
+        self.exchange(&[tokens::STOP_TRAN], &mut [0; 1]).await?;
+        self.wait_available_state(write_timeout_ms).await
+    }
+
+    /// Read count of well written blocks of the last multi-block write.
+    async fn read_written_blocks(&mut self) -> Result<u32, BusError<B>> {
+        let mut data = [0; 4];
+
+        self.send_command_ready(commands::ACMD22, 0x0000_0000)
+            .await?;
+
+        self.read_data(&mut data, Config::READ_TIMEOUT_MS).await?;
+
+        Ok(u32::from_be_bytes(data))
+    }
+
+    /// Read SCR, `None` if the card rejects SEND_SCR.
+    async fn read_scr(&mut self) -> Result<Option<Scr>, BusError<B>> {
+        let mut scr_data: ScrData = Default::default();
+
+        if self.send_command(commands::ACMD51, 0x0000_0000).await? != R1::READY_STATE {
+            warn!("SD doesn't support SEND_SCR");
+            return Ok(None);
+        }
+
+        self.read_data(&mut scr_data, Config::READ_TIMEOUT_MS)
+            .await?;
+
+        Ok(Some(Scr::from(scr_data)))
+    }
+
+    /// Read SD Status, `None` if the card rejects SD_STATUS.
+    async fn read_sd_status(&mut self) -> Result<Option<SdStatus>, BusError<B>> {
+        let mut status = SdStatus::default();
+
+        let r1 = self.send_command(commands::ACMD13, 0x0000_0000).await?;
+        if r1 != R1::READY_STATE {
+            warn!("SD doesn't support SD_STATUS");
+            return Ok(None);
+        }
+
+        let r2 = R2::new(r1, self.receive().await?);
+        if r2 != R2::empty() {
+            return Err(Error::ErrorCommand(commands::ACMD13, r2));
+        }
+
+        self.read_data(&mut status.0, Config::READ_TIMEOUT_MS)
+            .await?;
+
+        Ok(Some(status))
+    }
+
+    /// Read CID.
+    async fn read_cid(&mut self) -> Result<Cid, BusError<B>> {
+        let mut cid_data: CidData = Default::default();
+
+        if self.send_command(commands::CMD10, 0x0000_0000).await? != R1::READY_STATE {
+            return Err(Error::RegisterReadError);
+        }
+
+        self.read_data(&mut cid_data, Config::READ_TIMEOUT_MS)
+            .await?;
+
+        Ok(Cid::from(cid_data))
+    }
+
+    /// Send SWITCH_FUNC and read the switch status.
+    async fn switch_function(&mut self, arg: u32) -> Result<SwitchStatus, BusError<B>> {
+        let mut status = SwitchStatus::default();
+
+        self.send_command_ready(commands::CMD6, arg).await?;
+        self.read_data(&mut status.0, Config::READ_TIMEOUT_MS)
+            .await?;
+
+        Ok(status)
+    }
+
+    /// Switch to high speed mode if supported, `None` if the card has no switch function.
+    async fn switch_high_speed(
+        &mut self,
+        csd: &Csd,
+        scr: Option<&Scr>,
+    ) -> Result<Option<SwitchStatus>, BusError<B>> {
+        if !card::should_switch_high_speed::<Config>(csd, scr) {
+            return Ok(None);
+        }
+
+        let status = self.switch_function(SWITCH_CHECK_HIGH_SPEED).await?;
+        info!(
+            "SD switch functions, max current: {} mA, access modes: 0b{:016b}",
+            status.max_current_ma(),
+            status
+                .supported_functions(SwitchStatus::ACCESS_MODE_GROUP)
+                .unwrap_or_default()
+        );
+
+        if !status.is_supported(SwitchStatus::ACCESS_MODE_GROUP, SwitchStatus::HIGH_SPEED) {
+            return Ok(Some(status));
+        }
+
+        let status = self.switch_function(SWITCH_SET_HIGH_SPEED).await?;
+        info!(
+            "SD switched access mode: {}, max current: {} mA",
+            status
+                .selected_function(SwitchStatus::ACCESS_MODE_GROUP)
+                .unwrap_or(SwitchStatus::NOT_SWITCHABLE),
+            status.max_current_ma()
+        );
+
+        Ok(Some(status))
+    }
+
+    /// Read CSD.
+    async fn read_csd(&mut self) -> Result<Csd, BusError<B>> {
+        let mut csd_data: CsdData = Default::default();
+
+        if self.send_command(commands::CMD9, 0x0000_0000).await? != R1::READY_STATE {
+            return Err(Error::RegisterReadError);
+        }
+
+        self.read_data(&mut csd_data, Config::READ_TIMEOUT_MS)
+            .await?;
+
+        Ok(Csd::from(csd_data))
+    }
+
+    /// Re-initialize lost card, the card must be the same as initialized one.
+    ///
+    /// On failure the driver is left not initialized.
+    async fn recover(&mut self) -> Result<(), BusError<B>> {
+        warn!("SD card is lost, re-initializing");
+
+        let state = self.state;
+        let status = &state.status;
+        let info = self.init().await.map_err(|err| {
+            error!("SD recovery failed: {}", defmt::Debug2Format(&err));
+            status.set(StatusFlag::ErrorOccured | StatusFlag::NotInitialized);
+            card::init_error(err)
+        })?;
+
+        if !info.is_same_card(&state.info) {
+            error!("SD card is changed");
+            status.set(StatusFlag::ErrorOccured | StatusFlag::NotInitialized);
+            return Err(Error::CardChanged);
+        }
+
+        info!("SD card is recovered");
+        status.set(Status::default());
+
+        Ok(())
+    }
+}
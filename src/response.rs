@@ -1,19 +1,341 @@
-/// R1 response bitset.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct R1Response(pub u8);
+use crate::{consts::tokens, Error};
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// R1 response, sent by the card after every command.
+    pub struct R1: u8 {
+        /// Card is in idle state and running the initializing process.
+        const IN_IDLE_STATE = 0x01;
+        /// Erase sequence was cleared before executing because an out of erase sequence
+        /// command was received.
+        const ERASE_RESET = 0x02;
+        /// Illegal command code was detected.
+        const ILLEGAL_COMMAND = 0x04;
+        /// CRC check of the last command failed.
+        const COM_CRC_ERROR = 0x08;
+        /// Error in the sequence of erase commands occurred.
+        const ERASE_SEQUENCE_ERROR = 0x10;
+        /// Misaligned address that did not match the block length was used in the command.
+        const ADDRESS_ERROR = 0x20;
+        /// Command's argument was outside the allowed range for this card.
+        const PARAMETER_ERROR = 0x40;
+    }
+}
 
-impl R1Response {
+impl R1 {
     /// In ready state.
-    pub const READY_STATE: R1Response = R1Response(0x00);
-    /// In idle state.
-    pub const IN_IDLE_STATE: R1Response = R1Response(0x01);
-    /// In idle state and illegal command.
-    pub const IN_IDLE_AND_ILLEGAL: R1Response = R1Response(0x01 | 0x04);
+    pub const READY_STATE: R1 = R1::empty();
+}
+
+bitflags! {
+    /// R2 response, card status sent after SEND_STATUS.
+    ///
+    /// The high byte holds [`R1`] flags.
+    pub struct R2: u16 {
+        /// Card is in idle state and running the initializing process.
+        const IN_IDLE_STATE = (R1::IN_IDLE_STATE.bits() as u16) << 8;
+        /// Erase sequence was cleared before executing.
+        const ERASE_RESET = (R1::ERASE_RESET.bits() as u16) << 8;
+        /// Illegal command code was detected.
+        const ILLEGAL_COMMAND = (R1::ILLEGAL_COMMAND.bits() as u16) << 8;
+        /// CRC check of the last command failed.
+        const COM_CRC_ERROR = (R1::COM_CRC_ERROR.bits() as u16) << 8;
+        /// Error in the sequence of erase commands occurred.
+        const ERASE_SEQUENCE_ERROR = (R1::ERASE_SEQUENCE_ERROR.bits() as u16) << 8;
+        /// Misaligned address was used in the command.
+        const ADDRESS_ERROR = (R1::ADDRESS_ERROR.bits() as u16) << 8;
+        /// Command's argument was outside the allowed range for this card.
+        const PARAMETER_ERROR = (R1::PARAMETER_ERROR.bits() as u16) << 8;
+        /// Card is locked by the user.
+        const CARD_IS_LOCKED = 0x01;
+        /// Write protected blocks were skipped while erasing, or lock/unlock command failed.
+        const WP_ERASE_SKIP = 0x02;
+        /// General or unknown error occurred during the operation.
+        const ERROR = 0x04;
+        /// Internal card controller error.
+        const CC_ERROR = 0x08;
+        /// Card internal ECC was applied but failed to correct the data.
+        const CARD_ECC_FAILED = 0x10;
+        /// Command tried to write a write protected block.
+        const WP_VIOLATION = 0x20;
+        /// Invalid selection of erase groups for erase.
+        const ERASE_PARAM = 0x40;
+        /// Command's argument was out of range, or CSD overwrite failed.
+        const OUT_OF_RANGE = 0x80;
+    }
+}
+
+impl R2 {
+    /// Creates a new [`R2`] from R1 and the second status byte.
+    pub fn new(r1: R1, status: u8) -> Self {
+        R2::from_bits_truncate(u16::from_be_bytes([r1.bits(), status]))
+    }
+
+    /// R1 part of the status.
+    pub fn r1(&self) -> R1 {
+        R1::from_bits_truncate((self.bits() >> 8) as u8)
+    }
+}
+
+impl From<R1> for R2 {
+    fn from(r1: R1) -> Self {
+        R2::new(r1, 0x00)
+    }
+}
+
+/// R7 response, sent by the card after SEND_IF_COND.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct R7 {
+    /// R1 part of the response.
+    pub r1: R1,
+    /// Command version, voltage accepted and echo-back of check pattern.
+    pub data: u32,
+}
+
+impl R7 {
+    /// Voltage accepted value of 2.7-3.6V.
+    pub const VOLTAGE_2V7_3V6: u8 = 0x01;
+
+    /// Creates a new [`R7`] from R1 and the trailing four bytes.
+    pub fn new(r1: R1, data: [u8; 4]) -> Self {
+        R7 {
+            r1,
+            data: u32::from_be_bytes(data),
+        }
+    }
+
+    /// Command version.
+    pub fn command_version(&self) -> u8 {
+        (self.data >> 28) as u8
+    }
+
+    /// Voltage accepted by the card, 0 if the supply voltage is not accepted.
+    pub fn voltage_accepted(&self) -> u8 {
+        ((self.data >> 8) & 0x0F) as u8
+    }
+
+    /// Echo-back of check pattern.
+    pub fn check_pattern(&self) -> u8 {
+        self.data as u8
+    }
+
+    /// Result of SEND_IF_COND sent with 2.7-3.6V and 0xAA check pattern.
+    pub fn result<T, S>(&self) -> Result<(), Error<T, S>> {
+        if self.check_pattern() != tokens::CMD8_STATUS {
+            Err(Error::CheckPatternMismatch)
+        } else if self.voltage_accepted() != Self::VOLTAGE_2V7_3V6 {
+            Err(Error::UnsupportedVoltage)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Data error token bitset, sent by the card instead of a data block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataErrorToken(pub u8);
+
+impl DataErrorToken {
+    /// General or unknown error.
+    const ERROR: u8 = 0x01;
+    /// Card controller error.
+    const CC_ERROR: u8 = 0x02;
+    /// Card ECC failed.
+    const CARD_ECC_FAILED: u8 = 0x04;
+    /// Out of range.
+    const OUT_OF_RANGE: u8 = 0x08;
     /// Invalid mask.
-    const INVALID_MASK: u8 = 0x80;
+    const INVALID_MASK: u8 = 0xF0;
 
-    /// Check if R1 response is valid.
+    /// Check if token is a data error token.
     pub fn is_valid(&self) -> bool {
-        (self.0 & Self::INVALID_MASK) == 0x00
+        self.0 != 0x00 && (self.0 & Self::INVALID_MASK) == 0x00
+    }
+
+    /// Error of the token, the most specific flag wins.
+    pub fn error<T, S>(&self) -> Error<T, S> {
+        if !self.is_valid() {
+            Error::ReadError
+        } else if (self.0 & Self::OUT_OF_RANGE) != 0 {
+            Error::OutOfRange
+        } else if (self.0 & Self::CARD_ECC_FAILED) != 0 {
+            Error::CardEccFailed
+        } else if (self.0 & Self::CC_ERROR) != 0 {
+            Error::CardControllerError
+        } else {
+            debug_assert!((self.0 & Self::ERROR) != 0);
+            Error::CardError
+        }
+    }
+}
+
+/// Data response token, sent by the card after a written data block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataResponse(pub u8);
+
+impl DataResponse {
+    /// Result of the written data block.
+    pub fn result<T, S>(&self) -> Result<(), Error<T, S>> {
+        match self.0 & tokens::DATA_RES_MASK {
+            tokens::DATA_RES_ACCEPTED => Ok(()),
+            tokens::DATA_RES_CRC_ERROR => Err(Error::WriteCrcError),
+            tokens::DATA_RES_WRITE_ERROR => Err(Error::WriteError),
+            _ => Err(Error::UnknownDataResponse(self.0)),
+        }
+    }
+}
+
+/// Switch function status, 512-bit data block sent by the card after SWITCH_FUNC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchStatus(pub [u8; 64]);
+
+impl SwitchStatus {
+    /// Access mode function group.
+    pub const ACCESS_MODE_GROUP: u8 = 1;
+    /// High speed function of access mode group.
+    pub const HIGH_SPEED: u8 = 1;
+    /// Function selection reported when the function can't be switched.
+    pub const NOT_SWITCHABLE: u8 = 0x0F;
+    /// Count of function groups.
+    const GROUP_COUNT: u8 = 6;
+
+    /// Max current consumption of selected functions in mA, 0 on error.
+    pub fn max_current_ma(&self) -> u16 {
+        u16::from_be_bytes([self.0[0], self.0[1]])
+    }
+
+    /// Bitset of functions supported in the group 1-6, `None` for other groups.
+    pub fn supported_functions(&self, group: u8) -> Option<u16> {
+        let index = Self::group_index(group)?;
+        let offset = 2 + index * 2;
+
+        Some(u16::from_be_bytes([self.0[offset], self.0[offset + 1]]))
+    }
+
+    /// Check if the function is supported in the group 1-6.
+    pub fn is_supported(&self, group: u8, function: u8) -> bool {
+        function < 16
+            && self
+                .supported_functions(group)
+                .is_some_and(|functions| functions & (1 << function) != 0)
+    }
+
+    /// Function selected in the group 1-6, [`SwitchStatus::NOT_SWITCHABLE`] on error,
+    /// `None` for other groups.
+    pub fn selected_function(&self, group: u8) -> Option<u8> {
+        let byte = self.0[14 + Self::group_index(group)? / 2];
+
+        Some(if group.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        })
+    }
+
+    /// Version of the data structure.
+    pub fn version(&self) -> u8 {
+        self.0[17]
+    }
+
+    /// Check if high speed is selected.
+    pub fn is_high_speed(&self) -> bool {
+        self.selected_function(Self::ACCESS_MODE_GROUP) == Some(Self::HIGH_SPEED)
+    }
+
+    /// Index of the group in the status fields, from the last group.
+    fn group_index(group: u8) -> Option<usize> {
+        (1..=Self::GROUP_COUNT)
+            .contains(&group)
+            .then(|| usize::from(Self::GROUP_COUNT - group))
+    }
+}
+
+impl Default for SwitchStatus {
+    fn default() -> Self {
+        SwitchStatus([0; 64])
+    }
+}
+
+/// SD Status, 512-bit data block sent by the card after SD_STATUS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdStatus(pub [u8; 64]);
+
+impl SdStatus {
+    /// AU sizes in KiB by AU_SIZE value, 0 is not defined.
+    const AU_SIZES_KIB: [u32; 16] = [
+        0, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 12288, 16384, 24576, 32768, 65536,
+    ];
+    /// Speed classes by SPEED_CLASS value.
+    const SPEED_CLASSES: [u8; 5] = [0, 2, 4, 6, 10];
+
+    /// Size of protected area, in bytes for SDHC and later,
+    /// in units of `C_SIZE_MULT` and `READ_BL_LEN` for SDSC.
+    pub fn protected_area_size(&self) -> u32 {
+        u32::from_be_bytes([self.0[4], self.0[5], self.0[6], self.0[7]])
+    }
+
+    /// Speed class in MB/s, 0 if not specified.
+    pub fn speed_class(&self) -> u8 {
+        Self::SPEED_CLASSES
+            .get(usize::from(self.0[8]))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// UHS speed grade in MB/s divided by 10, 0 if less than 10 MB/s.
+    pub fn uhs_speed_grade(&self) -> u8 {
+        self.0[14] >> 4
+    }
+
+    /// Video speed class in MB/s, 0 if not supported.
+    pub fn video_speed_class(&self) -> u8 {
+        self.0[15]
+    }
+
+    /// Size of allocation unit in blocks, 0 if not defined.
+    pub fn au_size_blocks(&self) -> u32 {
+        Self::AU_SIZES_KIB[usize::from(self.0[10] >> 4)] * 2
+    }
+
+    /// Count of AUs erased at a time for which [`SdStatus::erase_timeout_s`] is given,
+    /// 0 if erase timeout calculation is not supported.
+    pub fn erase_size(&self) -> u16 {
+        u16::from_be_bytes([self.0[11], self.0[12]])
+    }
+
+    /// Timeout of erasing [`SdStatus::erase_size`] AUs in seconds,
+    /// 0 if erase timeout calculation is not supported.
+    pub fn erase_timeout_s(&self) -> u8 {
+        self.0[13] >> 2
+    }
+
+    /// Fixed offset added to the erase timeout in seconds.
+    pub fn erase_offset_s(&self) -> u8 {
+        self.0[13] & 0x03
+    }
+
+    /// Timeout of erasing `block_count` blocks in milliseconds,
+    /// `None` if erase timeout calculation is not supported.
+    pub fn erase_timeout_ms(&self, block_count: u64) -> Option<u32> {
+        let au_size = u64::from(self.au_size_blocks());
+        let erase_size = u64::from(self.erase_size());
+        let erase_timeout = u64::from(self.erase_timeout_s());
+
+        if au_size == 0 || erase_size == 0 || erase_timeout == 0 {
+            return None;
+        }
+
+        let au_count = block_count.div_ceil(au_size);
+        let timeout_ms = (erase_timeout * 1000 * au_count).div_ceil(erase_size)
+            + u64::from(self.erase_offset_s()) * 1000;
+
+        Some(u32::try_from(timeout_ms).unwrap_or(u32::MAX))
+    }
+}
+
+impl Default for SdStatus {
+    fn default() -> Self {
+        SdStatus([0; 64])
     }
 }
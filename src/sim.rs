@@ -0,0 +1,1237 @@
+//! Simulated SD card for testing the driver on the host without hardware.
+
+mod clock;
+mod fault;
+
+pub use self::clock::SimClock;
+pub use self::fault::Fault;
+
+use self::fault::{Faults, Noise};
+use crate::{
+    consts::{commands, tokens, BLOCK_SIZE, BLOCK_SIZE_U64},
+    crc::{crc16, crc7},
+};
+
+use core::convert::Infallible;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs, io,
+    path::Path,
+    vec,
+    vec::Vec,
+};
+
+/// Kind of simulated card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimCardKind {
+    /// Standard capacity card, physical layer version 1.x.
+    SdscV1,
+    /// Standard capacity card, physical layer version 2.0 or later.
+    SdscV2,
+    /// High capacity card, up to 32 GiB.
+    Sdhc,
+    /// Extended capacity card, above 32 GiB and up to 2 TiB.
+    Sdxc,
+    /// Ultra capacity card, above 2 TiB, addressed with ADDRESS_EXTENSION.
+    Sduc,
+}
+
+impl SimCardKind {
+    /// Check if the card is block addressed.
+    fn is_high_capacity(&self) -> bool {
+        matches!(
+            self,
+            SimCardKind::Sdhc | SimCardKind::Sdxc | SimCardKind::Sduc
+        )
+    }
+}
+
+/// Command received by simulated card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimCommand {
+    /// Command index.
+    pub index: u8,
+    /// Command argument.
+    pub arg: u32,
+    /// Command is application specific (preceded by CMD55).
+    pub app: bool,
+}
+
+/// Data transfer state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DataState {
+    /// No data transfer.
+    None,
+    /// Sending blocks until CMD12 or count of blocks set by CMD23 is sent.
+    ReadMultiple { block: u64, remaining: Option<u32> },
+    /// Waiting for start block token.
+    WriteToken { block: u64, multiple: bool },
+    /// Receiving data block and CRC.
+    WriteData {
+        block: u64,
+        multiple: bool,
+        data: Vec<u8>,
+    },
+}
+
+/// Simulated SD card, answers SPI traffic byte by byte.
+///
+/// Implements embedded-hal 1.0 `SpiDevice` and `SpiBus`, embedded-hal 0.2 `Transfer`/`Write`
+/// and, with the `async` feature, embedded-hal-async `SpiDevice`, chip select is ignored.
+pub struct SimCard {
+    kind: SimCardKind,
+    image: Vec<u8>,
+    sparse: BTreeMap<u64, Vec<u8>>,
+    capacity_blocks: u64,
+    address_extension: Option<u32>,
+    spi_mode: bool,
+    ready: bool,
+    app_cmd: bool,
+    crc_enabled: bool,
+    init_polls: usize,
+    init_polls_left: usize,
+    init_time_us: u64,
+    init_started_us: Option<u64>,
+    busy_bytes: usize,
+    busy_time_us: u64,
+    erase_time_us: u64,
+    busy_until_us: u64,
+    voltage_window: u16,
+    taac: u8,
+    nsac: u8,
+    tran_speed: u8,
+    high_speed_supported: bool,
+    high_speed: bool,
+    spi_clock_hz: Option<u32>,
+    spi_clock_changes: Vec<u32>,
+    read_time_us: u64,
+    data_ready_us: u64,
+    clock: SimClock,
+    erase_busy_bytes: usize,
+    erase_single_block: bool,
+    erased_byte: u8,
+    cmd23_supported: bool,
+    au_size: u8,
+    erase_size: u16,
+    erase_timeout_s: u8,
+    erase_offset_s: u8,
+    block_count_set: Option<u32>,
+    serial_number: u32,
+    erase_start: Option<u64>,
+    erase_end: Option<u64>,
+    written_blocks: u32,
+    frame: Vec<u8>,
+    state: DataState,
+    output: VecDeque<u8>,
+    r1_position: Option<usize>,
+    commands: Vec<SimCommand>,
+    faults: Faults,
+    noise: Option<Noise>,
+}
+
+impl SimCard {
+    /// R1 in idle state.
+    const R1_IDLE: u8 = 0x01;
+    /// R1 illegal command.
+    const R1_ILLEGAL_COMMAND: u8 = 0x04;
+    /// R1 command CRC error.
+    const R1_COM_CRC_ERROR: u8 = 0x08;
+    /// R1 erase sequence error.
+    const R1_ERASE_SEQUENCE_ERROR: u8 = 0x10;
+    /// R1 address error.
+    const R1_ADDRESS_ERROR: u8 = 0x20;
+    /// R1 parameter error.
+    const R1_PARAMETER_ERROR: u8 = 0x40;
+    /// Data error token, out of range.
+    const DATA_ERROR_OUT_OF_RANGE: u8 = 0x08;
+    /// OCR power up status bit.
+    const OCR_POWER_UP: u32 = 0x8000_0000;
+    /// OCR card capacity status bit.
+    const OCR_CCS: u32 = 0x4000_0000;
+    /// OCR card capacity over 2 TB bit.
+    const OCR_CO2T: u32 = 0x0800_0000;
+    /// Voltage window 2.7-3.6V.
+    const VOLTAGE_WINDOW: u16 = 0x01FF;
+    /// Offset of voltage window in OCR.
+    const OCR_VOLTAGE_WINDOW_OFFSET: u32 = 15;
+    /// ACMD41 host capacity support bit.
+    const HCS: u32 = 0x4000_0000;
+    /// ACMD41 host supports over 2 TB bit.
+    const HO2T: u32 = 0x0800_0000;
+    /// Min capacity of SDXC in blocks, 32 GiB.
+    const SDXC_MIN_BLOCKS: u64 = 0x1_0000 * 1024;
+    /// Min capacity of SDUC in blocks, above 2 TiB.
+    const SDUC_MIN_BLOCKS: u64 = 0x40_0000 * 1024;
+    /// Erase sector size in blocks.
+    const ERASE_SECTOR_BLOCKS: u64 = 128;
+    /// Max SPI clock frequency in identification mode.
+    pub const MAX_INIT_CLOCK_HZ: u32 = 400_000;
+    /// Max SPI clock frequency in default speed mode.
+    pub const MAX_DEFAULT_SPEED_CLOCK_HZ: u32 = 25_000_000;
+    /// Max SPI clock frequency in high speed mode.
+    pub const MAX_HIGH_SPEED_CLOCK_HZ: u32 = 50_000_000;
+    /// TRAN_SPEED of high speed mode, 50 MHz.
+    const HIGH_SPEED_TRAN_SPEED: u8 = 0x5A;
+    /// Command class of SWITCH_FUNC.
+    const SWITCH_COMMAND_CLASS: u64 = 1 << 10;
+    /// Max current consumption reported in switch status, in mA.
+    const MAX_CURRENT_MA: u16 = 100;
+    /// Gap between response and data token.
+    const ACCESS_GAP: usize = 2;
+    /// Manufacturer ID of CID.
+    pub const MANUFACTURER_ID: u8 = 0x1B;
+    /// OEM/Application ID of CID.
+    pub const OEM_ID: [u8; 2] = *b"SM";
+    /// Product name of CID.
+    pub const PRODUCT_NAME: [u8; 5] = *b"SIMSD";
+    /// Size of protected area reported in SD Status.
+    pub const PROTECTED_AREA_SIZE: u32 = 0x0008_0000;
+    /// Default product serial number of CID.
+    pub const SERIAL_NUMBER: u32 = 0x1234_5678;
+
+    /// Creates a new zero filled [`SimCard`].
+    ///
+    /// `kind` - kind of card.
+    /// `block_count` - capacity in 512-byte blocks.
+    pub fn new(kind: SimCardKind, block_count: usize) -> Self {
+        Self::from_image(kind, vec![0; block_count * BLOCK_SIZE])
+    }
+
+    /// Creates a new zero filled [`SimCard`], blocks are allocated on the first write.
+    ///
+    /// Used for large cards, [`SimCard::image`] is empty.
+    /// Erased blocks read as zeros regardless of [`SimCard::with_erased_byte`].
+    pub fn sparse(kind: SimCardKind, block_count: u64) -> Self {
+        Self::with_storage(kind, Vec::new(), block_count)
+    }
+
+    /// Creates a new [`SimCard`] backed by in-memory image.
+    ///
+    /// Image length must be representable by the CSD of the card kind:
+    /// a multiple of 2 KiB for SDSC and a multiple of 512 KiB for SDHC.
+    pub fn from_image(kind: SimCardKind, image: Vec<u8>) -> Self {
+        let block_count = image.len() as u64 / BLOCK_SIZE_U64;
+
+        Self::with_storage(kind, image, block_count)
+    }
+
+    /// Creates a new [`SimCard`], blocks beyond the image are sparse.
+    fn with_storage(kind: SimCardKind, image: Vec<u8>, capacity_blocks: u64) -> Self {
+        let card = SimCard {
+            kind,
+            image,
+            sparse: BTreeMap::new(),
+            capacity_blocks,
+            address_extension: None,
+            spi_mode: false,
+            ready: false,
+            app_cmd: false,
+            crc_enabled: false,
+            init_polls: 0,
+            init_polls_left: 0,
+            init_time_us: 0,
+            init_started_us: None,
+            busy_bytes: 2,
+            busy_time_us: 0,
+            erase_time_us: 0,
+            busy_until_us: 0,
+            voltage_window: Self::VOLTAGE_WINDOW,
+            taac: 0x0E,
+            nsac: 0x00,
+            tran_speed: 0x32,
+            high_speed_supported: true,
+            high_speed: false,
+            spi_clock_hz: None,
+            spi_clock_changes: Vec::new(),
+            read_time_us: 0,
+            data_ready_us: 0,
+            clock: SimClock::new(),
+            erase_busy_bytes: 16,
+            erase_single_block: true,
+            erased_byte: 0x00,
+            cmd23_supported: kind.is_high_capacity(),
+            au_size: 0,
+            erase_size: 0,
+            erase_timeout_s: 0,
+            erase_offset_s: 0,
+            block_count_set: None,
+            serial_number: Self::SERIAL_NUMBER,
+            erase_start: None,
+            erase_end: None,
+            written_blocks: 0,
+            frame: Vec::new(),
+            state: DataState::None,
+            output: VecDeque::new(),
+            r1_position: None,
+            commands: Vec::new(),
+            faults: Faults::default(),
+            noise: None,
+        };
+
+        card.csd();
+
+        card
+    }
+
+    /// Creates a new [`SimCard`] backed by image loaded from file.
+    pub fn from_file<P: AsRef<Path>>(kind: SimCardKind, path: P) -> io::Result<Self> {
+        Ok(Self::from_image(kind, fs::read(path)?))
+    }
+
+    /// Saves image to file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, &self.image)
+    }
+
+    /// Sets count of ACMD41 polls answered as busy before the card is ready.
+    pub fn with_init_polls(mut self, polls: usize) -> Self {
+        self.init_polls = polls;
+        self
+    }
+
+    /// Sets time since the first ACMD41 before the card is ready, in microseconds.
+    pub fn with_init_time(mut self, us: u64) -> Self {
+        self.init_time_us = us;
+        self
+    }
+
+    /// Sets product serial number reported in CID.
+    pub fn with_serial_number(mut self, serial_number: u32) -> Self {
+        self.serial_number = serial_number;
+        self
+    }
+
+    /// Sets count of busy bytes after a block is written.
+    pub fn with_busy_bytes(mut self, busy_bytes: usize) -> Self {
+        self.busy_bytes = busy_bytes;
+        self
+    }
+
+    /// Sets busy time after a block is written, in microseconds.
+    pub fn with_busy_time(mut self, us: u64) -> Self {
+        self.busy_time_us = us;
+        self
+    }
+
+    /// Sets count of busy bytes after an erase.
+    pub fn with_erase_busy_bytes(mut self, busy_bytes: usize) -> Self {
+        self.erase_busy_bytes = busy_bytes;
+        self
+    }
+
+    /// Sets access time reported in CSD, TAAC and NSAC bytes.
+    pub fn with_access_time(mut self, taac: u8, nsac: u8) -> Self {
+        self.taac = taac;
+        self.nsac = nsac;
+        self
+    }
+
+    /// Sets voltage window reported in OCR, bit 0 is 2.7-2.8V, bit 8 is 3.5-3.6V.
+    ///
+    /// Empty window rejects the voltage of SEND_IF_COND.
+    pub fn with_voltage_window(mut self, voltage_window: u16) -> Self {
+        self.voltage_window = voltage_window;
+        self
+    }
+
+    /// Sets max data transfer rate reported in CSD, TRAN_SPEED byte.
+    pub fn with_tran_speed(mut self, tran_speed: u8) -> Self {
+        self.tran_speed = tran_speed;
+        self
+    }
+
+    /// Sets whether the card supports high speed mode.
+    pub fn with_high_speed(mut self, supported: bool) -> Self {
+        self.high_speed_supported = supported;
+        self
+    }
+
+    /// Sets time before an image block is sent, in microseconds.
+    pub fn with_read_time(mut self, us: u64) -> Self {
+        self.read_time_us = us;
+        self
+    }
+
+    /// Sets busy time after an erase, in microseconds.
+    pub fn with_erase_time(mut self, us: u64) -> Self {
+        self.erase_time_us = us;
+        self
+    }
+
+    /// Sets whether the card erases single blocks, otherwise whole erase sectors are erased.
+    pub fn with_erase_single_block(mut self, enabled: bool) -> Self {
+        self.erase_single_block = enabled;
+        self
+    }
+
+    /// Sets value of erased bytes, reported in SCR.
+    ///
+    /// Only 0x00 and 0xFF can be reported, other values are reported as 0x00.
+    pub fn with_erased_byte(mut self, erased_byte: u8) -> Self {
+        self.erased_byte = erased_byte;
+        self
+    }
+
+    /// Sets whether the card supports SET_BLOCK_COUNT, by default only SDHC does.
+    pub fn with_cmd23(mut self, supported: bool) -> Self {
+        self.cmd23_supported = supported;
+        self
+    }
+
+    /// Sets AU_SIZE value reported in SD Status, 0 by default (not defined).
+    pub fn with_au_size(mut self, au_size: u8) -> Self {
+        self.au_size = au_size;
+        self
+    }
+
+    /// Sets erase timeout reported in SD Status, not reported by default.
+    ///
+    /// `erase_size` - count of AUs erased in `timeout_s`.
+    /// `timeout_s` - erase timeout in seconds.
+    /// `offset_s` - fixed offset of erase timeout in seconds.
+    pub fn with_erase_timeout(mut self, erase_size: u16, timeout_s: u8, offset_s: u8) -> Self {
+        self.erase_size = erase_size;
+        self.erase_timeout_s = timeout_s;
+        self.erase_offset_s = offset_s;
+        self
+    }
+
+    /// Corrupts image data blocks sent or received by the card at random.
+    ///
+    /// `seed` - seed of the pseudo random sequence, runs with the same seed are reproducible.
+    /// `one_in` - every block is corrupted with probability `1 / one_in`, 0 disables the noise.
+    pub fn with_noise(mut self, seed: u64, one_in: u32) -> Self {
+        self.noise = Some(Noise::new(seed, one_in));
+        self
+    }
+
+    /// Adds fault to the script of the card.
+    pub fn with_fault(mut self, fault: Fault) -> Self {
+        self.inject(fault);
+        self
+    }
+
+    /// Adds fault to the script of the card.
+    pub fn inject(&mut self, fault: Fault) {
+        self.faults.push(fault);
+    }
+
+    /// Count of injected faults not yet applied.
+    pub fn pending_faults(&self) -> usize {
+        self.faults.len()
+    }
+
+    /// Removes all injected faults.
+    pub fn clear_faults(&mut self) {
+        self.faults.clear();
+    }
+
+    /// Virtual clock of the card, pass it to the driver as delay.
+    pub fn clock(&self) -> SimClock {
+        self.clock.clone()
+    }
+
+    /// Sets SPI clock frequency, the card ignores commands if the frequency is above
+    /// the max frequency of its current mode.
+    ///
+    /// Once set, every exchanged byte advances the clock by its time at the frequency.
+    pub fn set_spi_clock(&mut self, hz: u32) {
+        self.spi_clock_hz = Some(hz);
+        self.spi_clock_changes.push(hz);
+    }
+
+    /// SPI clock frequencies set so far.
+    pub fn spi_clock_changes(&self) -> &[u32] {
+        &self.spi_clock_changes
+    }
+
+    /// Check if the card is switched to high speed mode.
+    pub fn is_high_speed(&self) -> bool {
+        self.high_speed
+    }
+
+    /// Kind of card.
+    pub fn kind(&self) -> SimCardKind {
+        self.kind
+    }
+
+    /// Capacity in 512-byte blocks.
+    pub fn block_count(&self) -> u64 {
+        self.capacity_blocks
+    }
+
+    /// Data of the block, zeros if a sparse block was never written.
+    pub fn block(&self, block: u64) -> Vec<u8> {
+        let offset = block as usize * BLOCK_SIZE;
+
+        match self.image.get(offset..offset + BLOCK_SIZE) {
+            Some(data) => data.to_vec(),
+            None => self
+                .sparse
+                .get(&block)
+                .cloned()
+                .unwrap_or_else(|| vec![0; BLOCK_SIZE]),
+        }
+    }
+
+    /// Store data of the block.
+    fn store_block(&mut self, block: u64, data: &[u8]) {
+        let offset = block as usize * BLOCK_SIZE;
+
+        match self.image.get_mut(offset..offset + BLOCK_SIZE) {
+            Some(image) => image.copy_from_slice(data),
+            None => {
+                self.sparse.insert(block, data.to_vec());
+            }
+        }
+    }
+
+    /// Erase blocks from `start` to `end` inclusive.
+    fn erase_blocks(&mut self, start: u64, end: u64) {
+        let image_end = (self.image.len() / BLOCK_SIZE) as u64;
+
+        if start < image_end {
+            let range =
+                start as usize * BLOCK_SIZE..(end.min(image_end - 1) as usize + 1) * BLOCK_SIZE;
+            self.image[range].fill(self.erased_byte);
+        }
+        self.sparse
+            .retain(|block, _| !(start..=end).contains(block));
+    }
+
+    /// Card image.
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Mutable card image.
+    pub fn image_mut(&mut self) -> &mut [u8] {
+        &mut self.image
+    }
+
+    /// Commands received by the card.
+    pub fn commands(&self) -> &[SimCommand] {
+        &self.commands
+    }
+
+    /// Clears the log of received commands.
+    pub fn clear_commands(&mut self) {
+        self.commands.clear();
+    }
+
+    /// Simulates power cycle of the card, image is kept.
+    pub fn power_cycle(&mut self) {
+        self.spi_mode = false;
+        self.ready = false;
+        self.app_cmd = false;
+        self.crc_enabled = false;
+        self.init_polls_left = 0;
+        self.init_started_us = None;
+        self.high_speed = false;
+        self.busy_until_us = 0;
+        self.data_ready_us = 0;
+        self.erase_start = None;
+        self.erase_end = None;
+        self.frame.clear();
+        self.state = DataState::None;
+        self.output.clear();
+        self.r1_position = None;
+    }
+
+    /// Exchanges one byte, returns the byte clocked out by the card.
+    pub fn exchange(&mut self, byte: u8) -> u8 {
+        if let Some(hz) = self.spi_clock_hz {
+            self.clock
+                .advance_ns(8_000_000_000u64.div_ceil(u64::from(hz)));
+        }
+
+        let now = self.clock.now_us();
+        let response = match self.output.front() {
+            Some(&tokens::DATA_START_BLOCK) if now < self.data_ready_us => tokens::AVAILABLE,
+            Some(&byte) => {
+                self.output.pop_front();
+                byte
+            }
+            None if now < self.busy_until_us => 0x00,
+            None => tokens::AVAILABLE,
+        };
+
+        match &mut self.state {
+            DataState::WriteData { data, .. } => {
+                data.push(byte);
+                if data.len() == BLOCK_SIZE + 2 {
+                    self.complete_write();
+                }
+            }
+            _ if !self.frame.is_empty() || (byte & 0xC0) == commands::CMD_BASE => {
+                self.frame.push(byte);
+                if self.frame.len() == 6 {
+                    self.complete_command();
+                }
+            }
+            DataState::WriteToken { block, multiple } => {
+                let (block, multiple) = (*block, *multiple);
+
+                if (!multiple && byte == tokens::DATA_START_BLOCK)
+                    || (multiple && byte == tokens::WRITE_MULTIPLE)
+                {
+                    self.state = DataState::WriteData {
+                        block,
+                        multiple,
+                        data: Vec::with_capacity(BLOCK_SIZE + 2),
+                    };
+                } else if multiple && byte == tokens::STOP_TRAN {
+                    self.state = DataState::None;
+                    self.push_busy(self.busy_bytes, self.busy_time_us);
+                }
+            }
+            _ => {}
+        }
+
+        if let DataState::ReadMultiple { block, remaining } = self.state {
+            if self.output.is_empty() {
+                self.push_read_block(block);
+                if self.state != DataState::None {
+                    self.read_multiple(block + 1, remaining.map(|remaining| remaining - 1));
+                }
+            }
+        }
+
+        response
+    }
+
+    /// R1 with current idle state.
+    fn r1(&self, flags: u8) -> u8 {
+        if self.ready {
+            flags
+        } else {
+            flags | Self::R1_IDLE
+        }
+    }
+
+    /// Queue R1 response preceded by one NCR byte.
+    fn push_r1(&mut self, flags: u8) {
+        let r1 = self.r1(flags);
+
+        self.output.push_back(tokens::AVAILABLE);
+        self.output.push_back(r1);
+        self.r1_position = Some(self.output.len() - 1);
+    }
+
+    /// Queue busy bytes, the card stays busy for `busy_time_us` afterwards.
+    fn push_busy(&mut self, busy_bytes: usize, busy_time_us: u64) {
+        let busy_bytes = self.faults.busy(busy_bytes);
+
+        self.output.extend(core::iter::repeat_n(0x00, busy_bytes));
+        self.busy_until_us = self.clock.now_us() + busy_time_us;
+    }
+
+    /// Queue data block with start token and CRC.
+    ///
+    /// `noisy` - data may be corrupted by the noise of the card.
+    fn push_data(&mut self, data: &[u8], noisy: bool) {
+        let crc = crc16(data);
+        let mut data = data.to_vec();
+
+        self.output
+            .extend(core::iter::repeat_n(tokens::AVAILABLE, Self::ACCESS_GAP));
+
+        if let Some(token) = self.faults.data_error() {
+            self.output.push_back(token);
+            return;
+        }
+
+        self.faults.data(&mut data);
+        if let (true, Some(noise)) = (noisy, &mut self.noise) {
+            noise.apply(&mut data);
+        }
+
+        self.output.push_back(tokens::DATA_START_BLOCK);
+        self.output.extend(data);
+        self.output.extend(crc.to_be_bytes());
+    }
+
+    /// Queue image block, or out of range error token.
+    fn push_read_block(&mut self, block: u64) {
+        if block >= self.block_count() {
+            self.output
+                .extend(core::iter::repeat_n(tokens::AVAILABLE, Self::ACCESS_GAP));
+            self.output.push_back(Self::DATA_ERROR_OUT_OF_RANGE);
+            self.state = DataState::None;
+            return;
+        }
+
+        let data = self.block(block);
+
+        self.data_ready_us = self.clock.now_us() + self.read_time_us;
+        self.push_data(&data, true);
+    }
+
+    /// Continue multi-block read from `block`, stops if no blocks are `remaining`.
+    fn read_multiple(&mut self, block: u64, remaining: Option<u32>) {
+        self.state = if remaining == Some(0) {
+            DataState::None
+        } else {
+            DataState::ReadMultiple { block, remaining }
+        };
+    }
+
+    /// Max SPI clock frequency of current mode.
+    fn max_spi_clock_hz(&self) -> u32 {
+        if !self.ready {
+            Self::MAX_INIT_CLOCK_HZ
+        } else if self.high_speed {
+            Self::MAX_HIGH_SPEED_CLOCK_HZ
+        } else {
+            Self::MAX_DEFAULT_SPEED_CLOCK_HZ
+        }
+    }
+
+    /// Convert data address to block, according to the card addressing.
+    ///
+    /// `extension` is the address extension set by the preceding ADDRESS_EXTENSION.
+    fn block_address(&self, arg: u32, extension: Option<u32>) -> Result<u64, u8> {
+        let block = match self.kind {
+            SimCardKind::Sdhc | SimCardKind::Sdxc => u64::from(arg),
+            SimCardKind::Sduc => (u64::from(extension.unwrap_or(0)) << 32) | u64::from(arg),
+            SimCardKind::SdscV1 | SimCardKind::SdscV2 => {
+                if !u64::from(arg).is_multiple_of(BLOCK_SIZE_U64) {
+                    return Err(Self::R1_ADDRESS_ERROR);
+                }
+                u64::from(arg) / BLOCK_SIZE_U64
+            }
+        };
+
+        if block >= self.block_count() {
+            Err(Self::R1_PARAMETER_ERROR)
+        } else {
+            Ok(block)
+        }
+    }
+
+    /// Handle received command frame.
+    fn complete_command(&mut self) {
+        let frame = core::mem::take(&mut self.frame);
+        let index = frame[0] & !commands::CMD_BASE;
+        let arg = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+        let crc_valid = ((crc7(&frame[..5]) << 1) | 0x01) == frame[5];
+        let app = core::mem::take(&mut self.app_cmd);
+
+        if !self.spi_mode && index != 0 {
+            return;
+        }
+
+        self.commands.push(SimCommand { index, arg, app });
+
+        if self.faults.ignore_command(index) {
+            return;
+        }
+
+        if self.spi_clock_hz > Some(self.max_spi_clock_hz()) {
+            return;
+        }
+
+        self.output.clear();
+        self.data_ready_us = 0;
+        self.execute_command(index, arg, app, crc_valid);
+
+        if let Some(position) = self.r1_position.take() {
+            match self.faults.response(index, self.output[position]) {
+                Some(r1) => self.output[position] = r1,
+                None => self.output.clear(),
+            }
+        }
+    }
+
+    /// Execute command and queue its response.
+    fn execute_command(&mut self, index: u8, arg: u32, app: bool, crc_valid: bool) {
+        if (self.crc_enabled || index == 0 || index == 8) && !crc_valid {
+            self.push_r1(Self::R1_COM_CRC_ERROR);
+            return;
+        }
+
+        let block_count_set = self.block_count_set.take();
+        let address_extension = self.address_extension.take();
+
+        match (app, index) {
+            (_, 0) => {
+                self.power_cycle();
+                self.spi_mode = true;
+                self.push_r1(0);
+            }
+            (_, 8) if self.kind == SimCardKind::SdscV1 => self.push_r1(Self::R1_ILLEGAL_COMMAND),
+            (_, 8) => {
+                let voltage_accepted = if self.voltage_window != 0 {
+                    (arg >> 8) as u8 & 0x0F
+                } else {
+                    0x00
+                };
+                let check_pattern = self.faults.check_pattern(arg as u8);
+
+                self.push_r1(0);
+                self.output
+                    .extend([0x00, 0x00, voltage_accepted, check_pattern]);
+            }
+            (_, 55) => {
+                self.app_cmd = true;
+                self.push_r1(0);
+            }
+            (_, 59) => {
+                self.crc_enabled = (arg & 0x01) != 0;
+                self.push_r1(0);
+            }
+            (_, 58) => {
+                let mut ocr = u32::from(self.voltage_window) << Self::OCR_VOLTAGE_WINDOW_OFFSET;
+
+                if self.ready {
+                    ocr |= Self::OCR_POWER_UP;
+                    if self.kind.is_high_capacity() {
+                        ocr |= Self::OCR_CCS;
+                    }
+                    if self.kind == SimCardKind::Sduc {
+                        ocr |= Self::OCR_CO2T;
+                    }
+                }
+
+                self.push_r1(0);
+                self.output.extend(ocr.to_be_bytes());
+            }
+            (true, 41) => {
+                let host_supports_hc = match self.kind {
+                    SimCardKind::SdscV1 | SimCardKind::SdscV2 => true,
+                    SimCardKind::Sdhc | SimCardKind::Sdxc => (arg & Self::HCS) != 0,
+                    SimCardKind::Sduc => (arg & (Self::HCS | Self::HO2T)) == Self::HCS | Self::HO2T,
+                };
+
+                if host_supports_hc {
+                    let now = self.clock.now_us();
+
+                    let started = match self.init_started_us {
+                        Some(started) => {
+                            self.init_polls_left = self.init_polls_left.saturating_sub(1);
+                            started
+                        }
+                        None => {
+                            self.init_polls_left = self.init_polls;
+                            *self.init_started_us.insert(now)
+                        }
+                    };
+                    self.ready = self.init_polls_left == 0 && now - started >= self.init_time_us;
+                }
+
+                self.push_r1(0);
+            }
+            _ if !self.ready => self.push_r1(Self::R1_ILLEGAL_COMMAND),
+            (false, 6) if self.kind == SimCardKind::SdscV1 => {
+                self.push_r1(Self::R1_ILLEGAL_COMMAND)
+            }
+            (false, 6) => {
+                let selected = match arg & 0x0F {
+                    0x0F => u8::from(self.high_speed),
+                    0x00 => 0x00,
+                    0x01 if self.high_speed_supported => 0x01,
+                    _ => 0x0F,
+                };
+
+                if (arg & 0x8000_0000) != 0 && selected != 0x0F {
+                    self.high_speed = selected == 0x01;
+                }
+
+                let status = self.switch_status(selected);
+
+                self.push_r1(0);
+                self.push_data(&status, false);
+            }
+            (false, 9) => {
+                let csd = self.csd();
+
+                self.push_r1(0);
+                self.push_data(&csd, false);
+            }
+            (false, 10) => {
+                let cid = self.cid();
+
+                self.push_r1(0);
+                self.push_data(&cid, false);
+            }
+            (false, 12) => {
+                self.state = DataState::None;
+                self.output.push_back(tokens::AVAILABLE);
+                self.push_r1(0);
+            }
+            (true, 13) => {
+                let status = self.sd_status();
+
+                self.push_r1(0);
+                self.output.push_back(0x00);
+                self.push_data(&status, false);
+            }
+            (false, 13) => {
+                let status = self.faults.status();
+
+                self.push_r1(0);
+                self.output.push_back(status);
+            }
+            (false, 17) | (false, 18) => match self.block_address(arg, address_extension) {
+                Ok(block) => {
+                    self.push_r1(0);
+                    self.push_read_block(block);
+                    if index == 18 {
+                        let remaining = block_count_set.map(|count| count.saturating_sub(1));
+                        self.read_multiple(block + 1, remaining);
+                    }
+                }
+                Err(flags) => self.push_r1(flags),
+            },
+            (false, 24) | (false, 25) => match self.block_address(arg, address_extension) {
+                Ok(block) => {
+                    self.push_r1(0);
+                    self.written_blocks = 0;
+                    self.state = DataState::WriteToken {
+                        block,
+                        multiple: index == 25,
+                    };
+                }
+                Err(flags) => self.push_r1(flags),
+            },
+            (true, 22) => {
+                let written_blocks = self
+                    .faults
+                    .written_blocks(self.written_blocks)
+                    .to_be_bytes();
+
+                self.push_r1(0);
+                self.push_data(&written_blocks, false);
+            }
+            (false, 22) if self.kind == SimCardKind::Sduc => {
+                self.address_extension = Some(arg & 0x3F);
+                self.push_r1(0);
+            }
+            (true, 23) => self.push_r1(0),
+            (false, 23) if self.cmd23_supported => {
+                self.block_count_set = Some(arg);
+                self.push_r1(0);
+            }
+            (true, 51) => {
+                let scr = self.scr();
+
+                self.push_r1(0);
+                self.push_data(&scr, false);
+            }
+            (false, 32) | (false, 33) => match self.block_address(arg, address_extension) {
+                Ok(block) => {
+                    if index == 32 {
+                        self.erase_start = Some(block);
+                    } else {
+                        self.erase_end = Some(block);
+                    }
+                    self.push_r1(0);
+                }
+                Err(flags) => self.push_r1(flags),
+            },
+            (false, 38) => match (self.erase_start.take(), self.erase_end.take()) {
+                (Some(mut start), Some(mut end)) if start <= end => {
+                    if !self.erase_single_block {
+                        let sector = Self::ERASE_SECTOR_BLOCKS;
+
+                        start -= start % sector;
+                        end = (end / sector + 1) * sector - 1;
+                    }
+
+                    let end = end.min(self.block_count() - 1);
+
+                    self.erase_blocks(start, end);
+                    self.push_r1(0);
+                    self.push_busy(self.erase_busy_bytes, self.erase_time_us);
+                }
+                _ => self.push_r1(Self::R1_ERASE_SEQUENCE_ERROR),
+            },
+            _ => self.push_r1(Self::R1_ILLEGAL_COMMAND),
+        }
+    }
+
+    /// Handle received data block.
+    fn complete_write(&mut self) {
+        let DataState::WriteData {
+            block,
+            multiple,
+            data,
+        } = core::mem::replace(&mut self.state, DataState::None)
+        else {
+            return;
+        };
+        let mut data = data;
+        let (payload, crc) = data.split_at_mut(BLOCK_SIZE);
+
+        if let Some(noise) = &mut self.noise {
+            noise.apply(payload);
+        }
+
+        let response = if let Some(response) = self.faults.data_response() {
+            response
+        } else if self.crc_enabled && crc16(payload).to_be_bytes() != crc {
+            tokens::DATA_RES_CRC_ERROR
+        } else if block >= self.block_count() {
+            tokens::DATA_RES_WRITE_ERROR
+        } else {
+            self.store_block(block, payload);
+            self.written_blocks += 1;
+            tokens::DATA_RES_ACCEPTED
+        };
+
+        self.output.push_back(response);
+        self.push_busy(self.busy_bytes, self.busy_time_us);
+
+        if multiple {
+            self.state = DataState::WriteToken {
+                block: block + 1,
+                multiple,
+            };
+        }
+    }
+
+    /// Build CID register, revision 1.0 manufactured in June 2023.
+    fn cid(&self) -> [u8; 16] {
+        let mut data = [0; 16];
+
+        data[0] = Self::MANUFACTURER_ID;
+        data[1..3].copy_from_slice(&Self::OEM_ID);
+        data[3..8].copy_from_slice(&Self::PRODUCT_NAME);
+        data[8] = 0x10;
+        data[9..13].copy_from_slice(&self.serial_number.to_be_bytes());
+        data[13] = 0x01;
+        data[14] = 0x76;
+        data[15] = (crc7(&data[..15]) << 1) | 0x01;
+
+        data
+    }
+
+    /// Build switch status with selected access mode.
+    fn switch_status(&self, selected: u8) -> [u8; 64] {
+        let mut data = [0; 64];
+        let max_current = if selected == 0x0F {
+            0
+        } else {
+            Self::MAX_CURRENT_MA
+        };
+
+        data[0..2].copy_from_slice(&max_current.to_be_bytes());
+        for group in data[2..14].chunks_mut(2) {
+            group.copy_from_slice(&0x8001u16.to_be_bytes());
+        }
+        if self.high_speed_supported {
+            data[13] |= 0x02;
+        }
+        data[16] = selected;
+        data[17] = 0x01;
+
+        data
+    }
+
+    /// Build SD Status for card kind, speed class 10 and U1 for SDHC, class 2 for SDSC 2.0.
+    fn sd_status(&self) -> [u8; 64] {
+        let (speed_class, uhs_speed_grade, video_speed_class) = match self.kind {
+            SimCardKind::SdscV1 => (0x00, 0x00, 0x00),
+            SimCardKind::SdscV2 => (0x01, 0x00, 0x00),
+            SimCardKind::Sdhc | SimCardKind::Sdxc | SimCardKind::Sduc => (0x04, 0x01, 10),
+        };
+        let mut data = [0; 64];
+
+        data[4..8].copy_from_slice(&Self::PROTECTED_AREA_SIZE.to_be_bytes());
+        data[8] = speed_class;
+        data[10] = self.au_size << 4;
+        data[11..13].copy_from_slice(&self.erase_size.to_be_bytes());
+        data[13] = (self.erase_timeout_s << 2) | (self.erase_offset_s & 0x03);
+        data[14] = uhs_speed_grade << 4;
+        data[15] = video_speed_class;
+
+        data
+    }
+
+    /// Build SCR register for card kind, 1 and 4 bit bus.
+    fn scr(&self) -> [u8; 8] {
+        let (sd_spec, sd_security) = match self.kind {
+            SimCardKind::SdscV1 => (0x00, 0x00),
+            SimCardKind::SdscV2 => (0x02, 0x02),
+            SimCardKind::Sdhc => (0x02, 0x03),
+            SimCardKind::Sdxc | SimCardKind::Sduc => (0x02, 0x04),
+        };
+        let mut data = [0; 8];
+
+        data[0] = sd_spec;
+        data[1] = (sd_security << 4) | 0x05;
+        if self.erased_byte == 0xFF {
+            data[1] |= 0x80;
+        }
+        if self.kind.is_high_capacity() {
+            data[2] |= 0x80;
+        }
+        if self.cmd23_supported {
+            data[3] |= 0x02;
+        }
+
+        data
+    }
+
+    /// Build CSD register for card kind and image size.
+    fn csd(&self) -> [u8; 16] {
+        let blocks = self.block_count();
+        let tran_speed = if self.high_speed {
+            Self::HIGH_SPEED_TRAN_SPEED
+        } else {
+            self.tran_speed
+        };
+        let command_classes = match self.kind {
+            SimCardKind::SdscV1 => 0x5B5 & !Self::SWITCH_COMMAND_CLASS,
+            SimCardKind::SdscV2 | SimCardKind::Sdhc | SimCardKind::Sdxc | SimCardKind::Sduc => {
+                0x5B5
+            }
+        };
+        let mut csd = 0u128;
+        let mut set = |hi: u32, lo: u32, value: u64| {
+            let mask = (1u128 << (hi - lo + 1)) - 1;
+            csd = (csd & !(mask << lo)) | ((u128::from(value) & mask) << lo);
+        };
+
+        set(119, 112, u64::from(self.taac));
+        set(111, 104, u64::from(self.nsac));
+        set(103, 96, u64::from(tran_speed));
+        set(95, 84, command_classes);
+        set(83, 80, 9);
+        set(46, 46, u64::from(self.erase_single_block));
+        set(45, 39, Self::ERASE_SECTOR_BLOCKS - 1);
+        set(28, 26, 2);
+        set(25, 22, 9);
+
+        match self.kind {
+            SimCardKind::Sdhc | SimCardKind::Sdxc => {
+                let (min, max) = match self.kind {
+                    SimCardKind::Sdhc => (1, Self::SDXC_MIN_BLOCKS - 1),
+                    _ => (Self::SDXC_MIN_BLOCKS, Self::SDUC_MIN_BLOCKS - 1),
+                };
+                assert!(
+                    (min..=max).contains(&blocks) && blocks.is_multiple_of(1024),
+                    "SDHC and SDXC capacity must be a multiple of 512 KiB, SDXC above 32 GiB"
+                );
+                set(127, 126, 1);
+                set(69, 48, blocks / 1024 - 1);
+            }
+            SimCardKind::Sduc => {
+                assert!(
+                    blocks >= Self::SDUC_MIN_BLOCKS && blocks.is_multiple_of(1024),
+                    "SDUC capacity must be a multiple of 512 KiB above 2 TiB"
+                );
+                set(127, 126, 2);
+                set(75, 48, blocks / 1024 - 1);
+            }
+            SimCardKind::SdscV1 | SimCardKind::SdscV2 => {
+                let multiplier = (0..8)
+                    .rev()
+                    .find(|m| {
+                        let unit = 1u64 << (m + 2);
+                        blocks > 0 && blocks.is_multiple_of(unit) && blocks / unit <= 4096
+                    })
+                    .expect("SDSC image must be a multiple of 2 KiB and at most 1 GiB");
+
+                set(79, 79, 1);
+                set(73, 62, (blocks >> (multiplier + 2)) - 1);
+                set(49, 47, multiplier);
+            }
+        }
+
+        let mut data = csd.to_be_bytes();
+        data[15] = (crc7(&data[..15]) << 1) | 0x01;
+
+        data
+    }
+}
+
+impl embedded_hal_1::spi::ErrorType for SimCard {
+    type Error = Infallible;
+}
+
+impl embedded_hal_1::spi::SpiDevice for SimCard {
+    fn transaction(
+        &mut self,
+        operations: &mut [embedded_hal_1::spi::Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        use embedded_hal_1::spi::{Operation, SpiBus};
+
+        for operation in operations {
+            match operation {
+                Operation::Read(buf) => SpiBus::read(self, buf)?,
+                Operation::Write(buf) => SpiBus::write(self, buf)?,
+                Operation::Transfer(read, write) => SpiBus::transfer(self, read, write)?,
+                Operation::TransferInPlace(buf) => SpiBus::transfer_in_place(self, buf)?,
+                Operation::DelayNs(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::spi::SpiDevice for SimCard {
+    async fn transaction(
+        &mut self,
+        operations: &mut [embedded_hal_async::spi::Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        embedded_hal_1::spi::SpiDevice::transaction(self, operations)
+    }
+}
+
+impl embedded_hal_1::spi::SpiBus for SimCard {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        words
+            .iter_mut()
+            .for_each(|b| *b = self.exchange(tokens::AVAILABLE));
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        words.iter().for_each(|b| {
+            self.exchange(*b);
+        });
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        for i in 0..read.len().max(write.len()) {
+            let byte = self.exchange(write.get(i).copied().unwrap_or(0xFF));
+            if let Some(r) = read.get_mut(i) {
+                *r = byte;
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        words.iter_mut().for_each(|b| *b = self.exchange(*b));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl embedded_hal::blocking::spi::Transfer<u8> for SimCard {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        words.iter_mut().for_each(|b| *b = self.exchange(*b));
+        Ok(words)
+    }
+}
+
+impl embedded_hal::blocking::spi::Write<u8> for SimCard {
+    type Error = Infallible;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        words.iter().for_each(|b| {
+            self.exchange(*b);
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::{cell::Cell, rc::Rc};
+
+/// Virtual clock of simulated card.
+///
+/// Time advances when the driver delays and, once the SPI clock is set by
+/// [`SimCard::set_spi_clock`](super::SimCard::set_spi_clock), when bytes are exchanged.
+/// Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct SimClock(Rc<Cell<u64>>);
+
+impl SimClock {
+    /// Creates a new [`SimClock`] at zero time.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current time in nanoseconds.
+    pub fn now_ns(&self) -> u64 {
+        self.0.get()
+    }
+
+    /// Current time in microseconds.
+    pub fn now_us(&self) -> u64 {
+        self.now_ns() / 1000
+    }
+
+    /// Advances time by `ns` nanoseconds.
+    pub fn advance_ns(&self, ns: u64) {
+        self.0.set(self.0.get() + ns);
+    }
+
+    /// Advances time by `us` microseconds.
+    pub fn advance_us(&self, us: u64) {
+        self.advance_ns(us * 1000);
+    }
+}
+
+impl embedded_hal_1::delay::DelayNs for SimClock {
+    fn delay_ns(&mut self, ns: u32) {
+        self.advance_ns(u64::from(ns));
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::delay::DelayNs for SimClock {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.advance_ns(u64::from(ns));
+    }
+}
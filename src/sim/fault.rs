@@ -0,0 +1,181 @@
+use std::vec::Vec;
+
+/// Fault injected into [`SimCard`](super::SimCard).
+///
+/// Every fault applies to the next matching event and is consumed afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Card ignores the command, `count` times in a row.
+    IgnoreCommand { index: u8, count: usize },
+    /// Card executes the next command, but its response is lost.
+    DropResponse { index: u8 },
+    /// R1 of the next command is XORed with `mask`.
+    CorruptResponse { index: u8, mask: u8 },
+    /// Check pattern echoed back by the next SEND_IF_COND is XORed with `mask`.
+    CorruptCheckPattern { mask: u8 },
+    /// Next SEND_STATUS reports `bits` in the second byte of R2.
+    StatusError { bits: u8 },
+    /// Next data block sent by the card is replaced by data error token.
+    DataErrorToken { token: u8 },
+    /// Bit of the next data block sent by the card is flipped after CRC calculation.
+    FlipDataBit { bit: usize },
+    /// Next busy period lasts `bytes` bytes.
+    StretchBusy { bytes: usize },
+    /// Next written block is rejected with data response token.
+    RejectWrite { response: u8 },
+    /// Next SEND_NUM_WR_BLOCKS reports `count` well written blocks.
+    WrittenBlocks { count: u32 },
+}
+
+/// Scripted faults of simulated card.
+#[derive(Debug, Default)]
+pub(super) struct Faults(Vec<Fault>);
+
+impl Faults {
+    /// Add fault to the script.
+    pub fn push(&mut self, fault: Fault) {
+        self.0.push(fault);
+    }
+
+    /// Remove all faults.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Count of not yet applied faults.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Take first fault matching predicate.
+    fn take<F: Fn(&Fault) -> bool>(&mut self, predicate: F) -> Option<Fault> {
+        let position = self.0.iter().position(predicate)?;
+
+        Some(self.0.remove(position))
+    }
+
+    /// Check if the command must be ignored.
+    pub fn ignore_command(&mut self, index: u8) -> bool {
+        let fault = self.0.iter_mut().find(
+            |fault| matches!(fault, Fault::IgnoreCommand { index: i, count } if *i == index && *count > 0),
+        );
+
+        match fault {
+            Some(Fault::IgnoreCommand { count, .. }) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.take(|fault| *fault == Fault::IgnoreCommand { index, count: 0 });
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Apply faults to R1 of the command, `None` if the response is lost.
+    pub fn response(&mut self, index: u8, r1: u8) -> Option<u8> {
+        match self.take(|fault| match fault {
+            Fault::DropResponse { index: i } | Fault::CorruptResponse { index: i, .. } => {
+                *i == index
+            }
+            _ => false,
+        }) {
+            Some(Fault::DropResponse { .. }) => None,
+            Some(Fault::CorruptResponse { mask, .. }) => Some(r1 ^ mask),
+            _ => Some(r1),
+        }
+    }
+
+    /// Apply faults to data block sent by the card.
+    pub fn data(&mut self, data: &mut [u8]) {
+        if let Some(Fault::FlipDataBit { bit }) =
+            self.take(|fault| matches!(fault, Fault::FlipDataBit { .. }))
+        {
+            let bit = bit % (data.len() * 8);
+            data[bit / 8] ^= 0x80 >> (bit % 8);
+        }
+    }
+
+    /// Data error token sent instead of data block, `None` if the block is sent.
+    pub fn data_error(&mut self) -> Option<u8> {
+        match self.take(|fault| matches!(fault, Fault::DataErrorToken { .. })) {
+            Some(Fault::DataErrorToken { token }) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Check pattern echoed back by SEND_IF_COND.
+    pub fn check_pattern(&mut self, pattern: u8) -> u8 {
+        match self.take(|fault| matches!(fault, Fault::CorruptCheckPattern { .. })) {
+            Some(Fault::CorruptCheckPattern { mask }) => pattern ^ mask,
+            _ => pattern,
+        }
+    }
+
+    /// Second byte of R2 reported by SEND_STATUS.
+    pub fn status(&mut self) -> u8 {
+        match self.take(|fault| matches!(fault, Fault::StatusError { .. })) {
+            Some(Fault::StatusError { bits }) => bits,
+            _ => 0x00,
+        }
+    }
+
+    /// Length of busy period in bytes.
+    pub fn busy(&mut self, bytes: usize) -> usize {
+        match self.take(|fault| matches!(fault, Fault::StretchBusy { .. })) {
+            Some(Fault::StretchBusy { bytes }) => bytes,
+            _ => bytes,
+        }
+    }
+
+    /// Data response token of written block, `None` if the block is accepted as is.
+    pub fn data_response(&mut self) -> Option<u8> {
+        match self.take(|fault| matches!(fault, Fault::RejectWrite { .. })) {
+            Some(Fault::RejectWrite { response }) => Some(response),
+            _ => None,
+        }
+    }
+
+    /// Count of well written blocks reported by SEND_NUM_WR_BLOCKS.
+    pub fn written_blocks(&mut self, count: u32) -> u32 {
+        match self.take(|fault| matches!(fault, Fault::WrittenBlocks { .. })) {
+            Some(Fault::WrittenBlocks { count }) => count,
+            _ => count,
+        }
+    }
+}
+
+/// Random corruption of data blocks, xorshift based.
+#[derive(Debug)]
+pub(super) struct Noise {
+    state: u64,
+    one_in: u32,
+}
+
+impl Noise {
+    /// Creates a new [`Noise`].
+    pub fn new(seed: u64, one_in: u32) -> Self {
+        Noise {
+            state: seed | 0x01,
+            one_in,
+        }
+    }
+
+    /// Next pseudo random number.
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Flip random bit of data block, if the block is hit by the noise.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        if self.one_in == 0 || !self.next().is_multiple_of(u64::from(self.one_in)) {
+            return;
+        }
+
+        let bit = (self.next() % (data.len() as u64 * 8)) as usize;
+        data[bit / 8] ^= 0x80 >> (bit % 8);
+    }
+}
@@ -0,0 +1,339 @@
+use core::convert::Infallible;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal_1::{
+    digital::OutputPin,
+    spi::{Operation, SpiBus, SpiDevice},
+};
+use switch_hal::OutputSwitch;
+
+/// Represents SPI transport for [`SdMmcSpi`](crate::SdMmcSpi).
+pub trait Transport {
+    /// Transport error type.
+    type Error: core::fmt::Debug;
+    /// Select error type.
+    type SelectError: core::fmt::Debug;
+
+    /// Activate chip select.
+    fn select(&mut self) -> Result<(), Self::SelectError>;
+
+    /// Deactivate chip select.
+    fn unselect(&mut self) -> Result<(), Self::SelectError>;
+
+    /// Send data and replace it in place by received data.
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Send data, received data is discarded.
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receive data block payload by clocking out 0xFF bytes.
+    ///
+    /// DMA-capable transports may override it to transfer straight into `data`,
+    /// CRC is checked by the driver after the transfer.
+    fn receive_block(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        data.fill(0xFF);
+        self.transfer(data)
+    }
+
+    /// Send data block payload.
+    ///
+    /// DMA-capable transports may override it to transfer straight out of `data`,
+    /// CRC is computed by the driver after the transfer.
+    fn send_block(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.write(data)
+    }
+
+    /// Send command frame or token, then receive the response window by clocking out 0xFF bytes.
+    ///
+    /// Transports managing chip select per transfer override it to keep the card selected
+    /// between the command and its response.
+    fn command(&mut self, frame: &[u8], response: &mut [u8]) -> Result<(), Self::Error> {
+        self.write(frame)?;
+        response.fill(0xFF);
+        self.transfer(response)
+    }
+
+    /// Receive data block payload and CRC by clocking out 0xFF bytes.
+    ///
+    /// The payload is received by [`Transport::receive_block`] by default.
+    fn receive_data(&mut self, data: &mut [u8], crc: &mut [u8]) -> Result<(), Self::Error> {
+        self.receive_block(data)?;
+        crc.fill(0xFF);
+        self.transfer(crc)
+    }
+
+    /// Send data block token, payload and CRC, then receive the data response
+    /// by clocking out 0xFF bytes.
+    ///
+    /// The payload is sent by [`Transport::send_block`] by default.
+    fn send_data(
+        &mut self,
+        token: &[u8],
+        data: &[u8],
+        crc: &[u8],
+        response: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.write(token)?;
+        self.send_block(data)?;
+        self.write(crc)?;
+        response.fill(0xFF);
+        self.transfer(response)
+    }
+
+    /// Change SPI clock frequency.
+    ///
+    /// Called with the init frequency before the init sequence and with the data transfer
+    /// frequency after CSD is read. Does nothing by default.
+    fn set_clock(&mut self, _hz: u32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Callback changing SPI clock frequency, receives SPI and frequency in Hz.
+pub type ClockControl<Spi> = fn(&mut Spi, u32);
+
+/// Transport based on embedded-hal 0.2 [`Transfer`]/[`Write`] and [`OutputSwitch`] chip select.
+///
+/// `Spi` - SPI.
+/// `Cs` - Chip select output switch.
+pub struct SpiTransport<Spi, Cs> {
+    spi: Spi,
+    cs: Cs,
+    clock_control: Option<ClockControl<Spi>>,
+}
+
+impl<Spi, Cs> SpiTransport<Spi, Cs> {
+    /// Creates a new [`SpiTransport<Spi, Cs>`].
+    ///
+    /// `spi` - SPI instance.
+    /// `cs` - chip select output switch.
+    pub fn new(spi: Spi, cs: Cs) -> Self {
+        SpiTransport {
+            spi,
+            cs,
+            clock_control: None,
+        }
+    }
+
+    /// Sets callback changing SPI clock frequency.
+    pub fn with_clock_control(mut self, clock_control: ClockControl<Spi>) -> Self {
+        self.clock_control = Some(clock_control);
+        self
+    }
+
+    /// Mutable access to SPI.
+    pub fn spi_mut(&mut self) -> &mut Spi {
+        &mut self.spi
+    }
+
+    /// Releases SPI and chip select.
+    pub fn release(self) -> (Spi, Cs) {
+        (self.spi, self.cs)
+    }
+}
+
+impl<Spi, Cs: OutputSwitch> Transport for SpiTransport<Spi, Cs>
+where
+    Spi: Transfer<u8> + Write<u8, Error = <Spi as Transfer<u8>>::Error>,
+    <Spi as Transfer<u8>>::Error: core::fmt::Debug,
+    Cs::Error: core::fmt::Debug,
+{
+    type Error = <Spi as Transfer<u8>>::Error;
+    type SelectError = Cs::Error;
+
+    fn select(&mut self) -> Result<(), Self::SelectError> {
+        self.cs.on()
+    }
+
+    fn unselect(&mut self) -> Result<(), Self::SelectError> {
+        self.cs.off()
+    }
+
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.spi.transfer(data).map(|_| ())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.spi.write(data)
+    }
+
+    fn set_clock(&mut self, hz: u32) -> Result<(), Self::Error> {
+        if let Some(clock_control) = self.clock_control {
+            clock_control(&mut self.spi, hz);
+        }
+        Ok(())
+    }
+}
+
+/// Transport based on embedded-hal 1.0 [`SpiDevice`].
+///
+/// Chip select is managed by the SPI device. A command with its response window and
+/// the token, payload, CRC and response of a data block are sent in one transaction each,
+/// but chip select is asserted during the dummy clocks of the init sequence and released
+/// while the card is polled for being ready or for a data token. Use [`SpiBusTransport`]
+/// if the card or the bus sharing doesn't tolerate it.
+///
+/// `Spi` - SPI device.
+pub struct SpiDeviceTransport<Spi> {
+    spi: Spi,
+    clock_control: Option<ClockControl<Spi>>,
+}
+
+impl<Spi> SpiDeviceTransport<Spi> {
+    /// Creates a new [`SpiDeviceTransport<Spi>`].
+    ///
+    /// `spi` - SPI device instance.
+    pub fn new(spi: Spi) -> Self {
+        SpiDeviceTransport {
+            spi,
+            clock_control: None,
+        }
+    }
+
+    /// Sets callback changing SPI clock frequency.
+    pub fn with_clock_control(mut self, clock_control: ClockControl<Spi>) -> Self {
+        self.clock_control = Some(clock_control);
+        self
+    }
+
+    /// Mutable access to SPI device.
+    pub fn spi_mut(&mut self) -> &mut Spi {
+        &mut self.spi
+    }
+
+    /// Releases SPI device.
+    pub fn release(self) -> Spi {
+        self.spi
+    }
+}
+
+impl<Spi: SpiDevice> Transport for SpiDeviceTransport<Spi> {
+    type Error = Spi::Error;
+    type SelectError = Infallible;
+
+    fn select(&mut self) -> Result<(), Self::SelectError> {
+        Ok(())
+    }
+
+    fn unselect(&mut self) -> Result<(), Self::SelectError> {
+        Ok(())
+    }
+
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.spi.transfer_in_place(data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.spi.write(data)
+    }
+
+    fn command(&mut self, frame: &[u8], response: &mut [u8]) -> Result<(), Self::Error> {
+        response.fill(0xFF);
+        self.spi.transaction(&mut [
+            Operation::Write(frame),
+            Operation::TransferInPlace(response),
+        ])
+    }
+
+    fn receive_data(&mut self, data: &mut [u8], crc: &mut [u8]) -> Result<(), Self::Error> {
+        data.fill(0xFF);
+        crc.fill(0xFF);
+        self.spi.transaction(&mut [
+            Operation::TransferInPlace(data),
+            Operation::TransferInPlace(crc),
+        ])
+    }
+
+    fn send_data(
+        &mut self,
+        token: &[u8],
+        data: &[u8],
+        crc: &[u8],
+        response: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        response.fill(0xFF);
+        self.spi.transaction(&mut [
+            Operation::Write(token),
+            Operation::Write(data),
+            Operation::Write(crc),
+            Operation::TransferInPlace(response),
+        ])
+    }
+
+    fn set_clock(&mut self, hz: u32) -> Result<(), Self::Error> {
+        if let Some(clock_control) = self.clock_control {
+            clock_control(&mut self.spi, hz);
+        }
+        Ok(())
+    }
+}
+
+/// Transport based on embedded-hal 1.0 [`SpiBus`] and [`OutputPin`] chip select.
+///
+/// `Spi` - SPI bus.
+/// `Cs` - Chip select output pin, active low.
+pub struct SpiBusTransport<Spi, Cs> {
+    spi: Spi,
+    cs: Cs,
+    clock_control: Option<ClockControl<Spi>>,
+}
+
+impl<Spi, Cs> SpiBusTransport<Spi, Cs> {
+    /// Creates a new [`SpiBusTransport<Spi, Cs>`].
+    ///
+    /// `spi` - SPI bus instance.
+    /// `cs` - chip select output pin.
+    pub fn new(spi: Spi, cs: Cs) -> Self {
+        SpiBusTransport {
+            spi,
+            cs,
+            clock_control: None,
+        }
+    }
+
+    /// Sets callback changing SPI clock frequency.
+    pub fn with_clock_control(mut self, clock_control: ClockControl<Spi>) -> Self {
+        self.clock_control = Some(clock_control);
+        self
+    }
+
+    /// Mutable access to SPI bus.
+    pub fn spi_mut(&mut self) -> &mut Spi {
+        &mut self.spi
+    }
+
+    /// Releases SPI bus and chip select.
+    pub fn release(self) -> (Spi, Cs) {
+        (self.spi, self.cs)
+    }
+}
+
+impl<Spi: SpiBus, Cs: OutputPin> Transport for SpiBusTransport<Spi, Cs> {
+    type Error = Spi::Error;
+    type SelectError = Cs::Error;
+
+    fn select(&mut self) -> Result<(), Self::SelectError> {
+        self.cs.set_low()
+    }
+
+    fn unselect(&mut self) -> Result<(), Self::SelectError> {
+        self.cs.set_high()
+    }
+
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.spi.transfer_in_place(data)?;
+        self.spi.flush()
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.spi.write(data)?;
+        self.spi.flush()
+    }
+
+    fn set_clock(&mut self, hz: u32) -> Result<(), Self::Error> {
+        if let Some(clock_control) = self.clock_control {
+            self.spi.flush()?;
+            clock_control(&mut self.spi, hz);
+        }
+        Ok(())
+    }
+}
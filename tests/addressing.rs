@@ -0,0 +1,98 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind},
+    DiskioDevice, DiskioError, Error,
+};
+
+fn data_commands(card: &SimCard) -> Vec<(u8, u32)> {
+    card.commands()
+        .iter()
+        .filter(|cmd| !cmd.app && matches!(cmd.index, 17 | 18 | 22 | 24 | 25))
+        .map(|cmd| (cmd.index, cmd.arg))
+        .collect()
+}
+
+#[test]
+fn sdxc_high_block() {
+    // 64 GiB.
+    let mut card = SimCard::sparse(SimCardKind::Sdxc, 0x800_0000);
+    let lba = 0x7FF_FFFF;
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    sd.write(&pattern(lba), lba as u64).unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, lba as u64).unwrap();
+    assert_eq!(buf, pattern(lba));
+
+    assert_eq!(card.block(lba as u64), pattern(lba));
+    assert_eq!(data_commands(&card), [(24, 0x7FF_FFFF), (17, 0x7FF_FFFF)]);
+}
+
+#[test]
+fn sduc_address_extension() {
+    // 4 TiB.
+    let mut card = SimCard::sparse(SimCardKind::Sduc, 0x2_0000_0000);
+    let lba = 0x1_0000_0005;
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    sd.write(&pattern(lba), lba as u64).unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, lba as u64).unwrap();
+    assert_eq!(buf, pattern(lba));
+
+    // The same low address without extension is a different block.
+    sd.read(&mut buf, 5).unwrap();
+    assert_eq!(buf, [0; BLOCK_SIZE]);
+
+    assert_eq!(card.block(lba as u64), pattern(lba));
+    assert_eq!(
+        data_commands(&card),
+        [(22, 1), (24, 5), (22, 1), (17, 5), (22, 0), (17, 5)]
+    );
+}
+
+#[test]
+fn sdhc_beyond_block_address() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, 2048);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    assert!(matches!(
+        sd.read(&mut buf, 1 << 32),
+        Err(DiskioError::Hardware(Error::OutOfRange))
+    ));
+
+    let mut buf = [0; 2 * BLOCK_SIZE];
+    assert!(matches!(
+        sd.read(&mut buf, u64::from(u32::MAX)),
+        Err(DiskioError::Hardware(Error::OutOfRange))
+    ));
+    assert!(matches!(
+        sd.write(&buf, u64::from(u32::MAX)),
+        Err(DiskioError::Hardware(Error::OutOfRange))
+    ));
+
+    assert!(data_commands(&card).is_empty());
+}
+
+#[test]
+fn sdsc_beyond_byte_address() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, 2048);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    assert!(matches!(
+        sd.read(&mut buf, 0x80_0000),
+        Err(DiskioError::Hardware(Error::OutOfRange))
+    ));
+
+    assert!(data_commands(&card).is_empty());
+}
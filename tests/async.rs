@@ -0,0 +1,390 @@
+mod common;
+
+use common::{pattern, BLOCK_SIZE};
+use core::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+use sdmmc_spi::{
+    sim::{Fault, SimCard, SimCardKind, SimClock},
+    AsyncSdMmcSpi, CardType, DefaultSdMmcSpiConfig, DiskioError, Error, IoctlCmd, SdMmcSpiConfig,
+    StatusFlag,
+};
+
+const BLOCK_COUNT: usize = 4096;
+const INIT_CLOCK_HZ: u32 = DefaultSdMmcSpiConfig::INIT_CLOCK_HZ;
+const RETRIES: usize = DefaultSdMmcSpiConfig::TRANSFER_RETRIES;
+
+struct DefaultSpeedConfig;
+
+impl SdMmcSpiConfig for DefaultSpeedConfig {
+    const HIGH_SPEED: bool = false;
+}
+
+struct RecoveryConfig;
+
+impl SdMmcSpiConfig for RecoveryConfig {
+    const RECOVERY_ATTEMPTS: usize = 1;
+}
+
+type Driver<'a, Config = DefaultSdMmcSpiConfig> = AsyncSdMmcSpi<&'a mut SimCard, SimClock, Config>;
+
+/// Polls the future to completion, the simulated card never leaves it pending.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+fn set_spi_clock(card: &mut &mut SimCard, hz: u32) {
+    card.set_spi_clock(hz);
+}
+
+fn driver<Config: SdMmcSpiConfig>(card: &mut SimCard) -> Driver<'_, Config> {
+    let clock = card.clock();
+
+    AsyncSdMmcSpi::new(card, clock).with_clock_control(set_spi_clock)
+}
+
+fn initialized(card: &mut SimCard) -> Driver<'_> {
+    let mut sd = driver(card);
+    block_on(sd.init()).unwrap();
+    sd
+}
+
+#[test]
+fn init() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_serial_number(0xCAFE_0002);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card);
+
+    assert!(sd.cid().is_none());
+    block_on(sd.init()).unwrap();
+
+    assert!(sd.status().is_empty());
+    assert_eq!(sd.card_type(), Some(CardType::SDHC));
+    assert_eq!(sd.card_capacity_blocks(), Some(BLOCK_COUNT as u64));
+    assert_eq!(sd.cid().unwrap().serial_number(), 0xCAFE_0002);
+    assert!(sd.scr().unwrap().cmd23_supported());
+    assert!(sd.switch_status().is_some());
+    assert!(sd.sd_status().is_some());
+    assert!(matches!(
+        block_on(sd.init()),
+        Err(DiskioError::AlreadyInitialized)
+    ));
+
+    assert!(card.is_high_speed());
+    assert_eq!(card.spi_clock_changes(), [INIT_CLOCK_HZ, 50_000_000]);
+}
+
+#[test]
+fn init_default_speed() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = driver::<DefaultSpeedConfig>(&mut card);
+    block_on(sd.init()).unwrap();
+
+    assert!(sd.switch_status().is_none());
+    assert!(!card.is_high_speed());
+    assert!(!card.commands().iter().any(|c| c.index == 6));
+    assert_eq!(card.spi_clock_changes(), [INIT_CLOCK_HZ, 25_000_000]);
+}
+
+#[test]
+fn init_sdsc_v1() {
+    let mut card = SimCard::new(SimCardKind::SdscV1, BLOCK_COUNT);
+    let sd = initialized(&mut card);
+
+    assert_eq!(sd.card_type(), Some(CardType::SD1));
+    assert_eq!(sd.card_capacity_blocks(), Some(BLOCK_COUNT as u64));
+}
+
+#[test]
+fn init_unsupported_voltage() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_voltage_window(0x001);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card);
+
+    assert!(matches!(
+        block_on(sd.init()),
+        Err(DiskioError::Hardware(Error::UnsupportedVoltage))
+    ));
+    assert!(sd.status().contains(StatusFlag::ErrorOccured));
+    assert!(sd.status().contains(StatusFlag::NotInitialized));
+}
+
+#[test]
+fn init_card_not_found() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_fault(Fault::IgnoreCommand {
+        index: 0,
+        count: DefaultSdMmcSpiConfig::ENTER_SPI_MODE_ATTEMPTS,
+    });
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card);
+
+    assert!(matches!(
+        block_on(sd.init()),
+        Err(DiskioError::Hardware(Error::CardNotFound))
+    ));
+    assert!(sd.status().contains(StatusFlag::NotInitialized));
+}
+
+#[test]
+fn read_write() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    let blocks: Vec<u8> = (10..14).flat_map(pattern).collect();
+    block_on(sd.write(&pattern(0), 0)).unwrap();
+    block_on(sd.write(&blocks, 10)).unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    block_on(sd.read(&mut buf, 0)).unwrap();
+    assert_eq!(buf, pattern(0));
+
+    let mut buf = vec![0; 4 * BLOCK_SIZE];
+    block_on(sd.read(&mut buf, 10)).unwrap();
+    assert_eq!(buf, blocks);
+    block_on(sd.sync()).unwrap();
+
+    assert_eq!(card.block(0), pattern(0));
+    assert_eq!(card.block(13), pattern(13));
+    let multi_block: Vec<_> = card
+        .commands()
+        .iter()
+        .filter(|c| c.index == 23 || c.index == 18 || c.index == 25)
+        .map(|c| (c.index, c.app, c.arg))
+        .collect();
+    assert_eq!(
+        multi_block,
+        [
+            (23, true, 4),
+            (25, false, 10),
+            (23, false, 4),
+            (18, false, 10)
+        ]
+    );
+}
+
+#[test]
+fn read_write_sdsc_v1() {
+    let mut card = SimCard::new(SimCardKind::SdscV1, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    let blocks: Vec<u8> = (5..8).flat_map(pattern).collect();
+    block_on(sd.write(&blocks, 5)).unwrap();
+
+    let mut buf = vec![0; 3 * BLOCK_SIZE];
+    block_on(sd.read(&mut buf, 5)).unwrap();
+    assert_eq!(buf, blocks);
+    assert!(!card.commands().iter().any(|c| c.index == 23 && !c.app));
+    assert!(card.commands().iter().any(|c| c.index == 23 && c.app));
+}
+
+#[test]
+fn not_initialized() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card);
+    let mut buf = [0; BLOCK_SIZE];
+
+    assert!(matches!(
+        block_on(sd.read(&mut buf, 0)),
+        Err(DiskioError::NotInitialized)
+    ));
+    assert!(matches!(
+        block_on(sd.write(&buf, 0)),
+        Err(DiskioError::NotInitialized)
+    ));
+    assert!(matches!(
+        block_on(sd.erase(0, 0)),
+        Err(DiskioError::NotInitialized)
+    ));
+    assert!(matches!(
+        block_on(sd.ioctl(IoctlCmd::CtrlSync)),
+        Err(DiskioError::NotInitialized)
+    ));
+
+    block_on(sd.init()).unwrap();
+    sd.reset();
+    assert!(matches!(
+        block_on(sd.read(&mut buf, 0)),
+        Err(DiskioError::NotInitialized)
+    ));
+}
+
+#[test]
+fn invalid_arguments() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let mut buf = [0; BLOCK_SIZE + 1];
+
+    assert!(matches!(
+        block_on(sd.read(&mut buf, 0)),
+        Err(DiskioError::InvalidArgument)
+    ));
+    assert!(matches!(
+        block_on(sd.write(&[], 0)),
+        Err(DiskioError::InvalidArgument)
+    ));
+    assert!(matches!(
+        block_on(sd.erase(0, BLOCK_COUNT as u64)),
+        Err(DiskioError::InvalidArgument)
+    ));
+}
+
+#[test]
+fn out_of_range() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let mut buf = [0; 2 * BLOCK_SIZE];
+
+    assert!(matches!(
+        block_on(sd.read(&mut buf, u64::MAX)),
+        Err(DiskioError::Hardware(Error::OutOfRange))
+    ));
+    assert!(matches!(
+        block_on(sd.write(&buf, u64::from(u32::MAX))),
+        Err(DiskioError::Hardware(Error::OutOfRange))
+    ));
+}
+
+#[test]
+fn read_crc_error() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    block_on(sd.write(&pattern(3), 3)).unwrap();
+    let mut buf = [0; BLOCK_SIZE];
+
+    sd.spi_mut().inject(Fault::FlipDataBit { bit: 100 });
+    block_on(sd.read(&mut buf, 3)).unwrap();
+    assert_eq!(buf, pattern(3));
+
+    for _ in 0..=RETRIES {
+        sd.spi_mut().inject(Fault::FlipDataBit { bit: 100 });
+    }
+    assert!(matches!(
+        block_on(sd.read(&mut buf, 3)),
+        Err(DiskioError::Hardware(Error::CrcError(_, _)))
+    ));
+
+    block_on(sd.read(&mut buf, 3)).unwrap();
+    assert_eq!(buf, pattern(3));
+}
+
+#[test]
+fn write_rejected() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    sd.spi_mut().inject(Fault::RejectWrite { response: 0x0B });
+    block_on(sd.write(&pattern(1), 1)).unwrap();
+
+    for _ in 0..=RETRIES {
+        sd.spi_mut().inject(Fault::RejectWrite { response: 0x0B });
+    }
+    assert!(matches!(
+        block_on(sd.write(&pattern(1), 1)),
+        Err(DiskioError::Hardware(Error::WriteCrcError))
+    ));
+
+    sd.spi_mut().inject(Fault::RejectWrite { response: 0x0D });
+    let err = block_on(sd.write_blocks(&[pattern(1), pattern(2)].concat(), 1)).unwrap_err();
+    assert_eq!(err.blocks, 0);
+    assert!(matches!(
+        err.error,
+        DiskioError::Hardware(Error::WriteError)
+    ));
+    block_on(sd.write(&pattern(1), 1)).unwrap();
+}
+
+#[test]
+fn partial_read() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let mut buf = [0; 4 * BLOCK_SIZE];
+
+    let err = block_on(sd.read_blocks(&mut buf, BLOCK_COUNT as u64 - 2)).unwrap_err();
+    assert_eq!(err.blocks, 2);
+    assert!(matches!(
+        err.error,
+        DiskioError::Hardware(Error::OutOfRange)
+    ));
+}
+
+#[test]
+fn recovers_lost_card() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = driver::<RecoveryConfig>(&mut card);
+    block_on(sd.init()).unwrap();
+    block_on(sd.write(&pattern(5), 5)).unwrap();
+
+    sd.spi_mut().power_cycle();
+    let mut buf = [0; BLOCK_SIZE];
+    block_on(sd.read(&mut buf, 5)).unwrap();
+    assert_eq!(buf, pattern(5));
+    assert!(sd.status().is_empty());
+
+    let cmd0_count = card.commands().iter().filter(|c| c.index == 0).count();
+    assert_eq!(cmd0_count, 2);
+}
+
+#[test]
+fn ioctl() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_au_size(0x01);
+    for block in 0..64 {
+        card.image_mut()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+            .copy_from_slice(&pattern(block));
+    }
+    let mut sd = initialized(&mut card);
+
+    let mut sector_count = 0;
+    block_on(sd.ioctl(IoctlCmd::GetSectorCount(&mut sector_count))).unwrap();
+    assert_eq!(sector_count, BLOCK_COUNT as u64);
+
+    let mut sector_size = 0;
+    block_on(sd.ioctl(IoctlCmd::GetSectorSize(&mut sector_size))).unwrap();
+    assert_eq!(sector_size, BLOCK_SIZE);
+
+    let mut block_size = 0;
+    block_on(sd.ioctl(IoctlCmd::GetBlockSize(&mut block_size))).unwrap();
+    assert_eq!(block_size, 32);
+
+    block_on(sd.ioctl(IoctlCmd::CtrlSync)).unwrap();
+    block_on(sd.ioctl(IoctlCmd::CtrlTrim(&(3, 40)))).unwrap();
+
+    for block in 0..64 {
+        let erased = (3..=40).contains(&block);
+        assert_eq!(
+            card.block(block) == [0; BLOCK_SIZE],
+            erased,
+            "block {block}"
+        );
+    }
+}
+
+#[test]
+fn erase() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT);
+    for block in 0..8 {
+        card.image_mut()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+            .copy_from_slice(&pattern(block));
+    }
+    let mut sd = initialized(&mut card);
+
+    block_on(sd.erase(2, 4)).unwrap();
+    assert!(matches!(
+        block_on(sd.erase(4, 2)),
+        Err(DiskioError::InvalidArgument)
+    ));
+
+    for block in 0..8u64 {
+        let erased = (2..=4).contains(&block);
+        assert_eq!(
+            card.block(block) == [0; BLOCK_SIZE],
+            erased,
+            "block {block}"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind, SimClock},
+    ClockControl, DefaultSdMmcSpiConfig, DiskioDevice, DiskioError, Error, SdMmcSpi,
+    SdMmcSpiConfig, SpiDeviceTransport, SwitchStatus,
+};
+
+const BLOCK_COUNT: usize = 2048;
+const INIT_CLOCK_HZ: u32 = DefaultSdMmcSpiConfig::INIT_CLOCK_HZ;
+
+struct SlowConfig;
+
+impl SdMmcSpiConfig for SlowConfig {
+    const MAX_CLOCK_HZ: u32 = 10_000_000;
+}
+
+struct DefaultSpeedConfig;
+
+impl SdMmcSpiConfig for DefaultSpeedConfig {
+    const HIGH_SPEED: bool = false;
+}
+
+type Driver<'a, Config> = SdMmcSpi<SpiDeviceTransport<&'a mut SimCard>, SimClock, Config>;
+
+fn set_spi_clock(card: &mut &mut SimCard, hz: u32) {
+    card.set_spi_clock(hz);
+}
+
+fn driver<'a, Config: SdMmcSpiConfig>(
+    card: &'a mut SimCard,
+    clock_control: ClockControl<&'a mut SimCard>,
+) -> Driver<'a, Config> {
+    let clock = card.clock();
+
+    Driver::with_transport(
+        SpiDeviceTransport::new(card).with_clock_control(clock_control),
+        clock,
+    )
+}
+
+#[test]
+fn switches_clock() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card, set_spi_clock);
+
+    sd.initialize().unwrap();
+    assert_eq!(sd.csd().unwrap().max_transfer_rate_hz(), 25_000_000);
+
+    sd.reset();
+    sd.initialize().unwrap();
+
+    assert!(card.is_high_speed());
+    assert_eq!(
+        card.spi_clock_changes(),
+        [INIT_CLOCK_HZ, 50_000_000, INIT_CLOCK_HZ, 50_000_000]
+    );
+}
+
+#[test]
+fn limited_by_tran_speed() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT)
+        .with_tran_speed(0x2A)
+        .with_high_speed(false);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card, set_spi_clock);
+
+    sd.initialize().unwrap();
+
+    assert_eq!(card.spi_clock_changes(), [INIT_CLOCK_HZ, 20_000_000]);
+}
+
+#[test]
+fn limited_by_config() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = driver::<SlowConfig>(&mut card, set_spi_clock);
+
+    sd.initialize().unwrap();
+
+    assert_eq!(card.spi_clock_changes(), [INIT_CLOCK_HZ, 10_000_000]);
+}
+
+#[test]
+fn fast_init_fails() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd =
+        driver::<DefaultSdMmcSpiConfig>(&mut card, |card, _| card.set_spi_clock(25_000_000));
+
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::Hardware(Error::CardNotFound))
+    ));
+}
+
+#[test]
+fn high_speed() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card, set_spi_clock);
+
+    sd.initialize().unwrap();
+
+    let status = sd.switch_status().unwrap();
+    assert!(status.is_high_speed());
+    assert!(status.is_supported(SwitchStatus::ACCESS_MODE_GROUP, SwitchStatus::HIGH_SPEED));
+    assert_eq!(status.max_current_ma(), 100);
+    assert_eq!(status.version(), 1);
+
+    for group in [0, 7, u8::MAX] {
+        assert_eq!(status.supported_functions(group), None);
+        assert_eq!(status.selected_function(group), None);
+        assert!(!status.is_supported(group, SwitchStatus::HIGH_SPEED));
+    }
+
+    let switches: Vec<u32> = card
+        .commands()
+        .iter()
+        .filter(|command| command.index == 6)
+        .map(|command| command.arg)
+        .collect();
+    assert_eq!(switches, [0x00FF_FFF1, 0x80FF_FFF1]);
+}
+
+#[test]
+fn high_speed_not_supported() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_high_speed(false);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card, set_spi_clock);
+
+    sd.initialize().unwrap();
+
+    let status = sd.switch_status().unwrap();
+    assert!(!status.is_high_speed());
+    assert!(!status.is_supported(SwitchStatus::ACCESS_MODE_GROUP, SwitchStatus::HIGH_SPEED));
+    assert_eq!(
+        status.selected_function(SwitchStatus::ACCESS_MODE_GROUP),
+        Some(SwitchStatus::NOT_SWITCHABLE)
+    );
+
+    assert!(!card.is_high_speed());
+    assert_eq!(card.spi_clock_changes(), [INIT_CLOCK_HZ, 25_000_000]);
+    assert_eq!(card.commands().iter().filter(|c| c.index == 6).count(), 1);
+}
+
+#[test]
+fn high_speed_disabled() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = driver::<DefaultSpeedConfig>(&mut card, set_spi_clock);
+
+    sd.initialize().unwrap();
+
+    assert!(sd.switch_status().is_none());
+    assert!(!card.commands().iter().any(|c| c.index == 6));
+    assert_eq!(card.spi_clock_changes(), [INIT_CLOCK_HZ, 25_000_000]);
+}
+
+#[test]
+fn no_switch_function() {
+    let mut card = SimCard::new(SimCardKind::SdscV1, BLOCK_COUNT);
+    let mut sd = driver::<DefaultSdMmcSpiConfig>(&mut card, set_spi_clock);
+
+    sd.initialize().unwrap();
+
+    assert!(sd.switch_status().is_none());
+    assert!(!card.commands().iter().any(|c| c.index == 6));
+    assert_eq!(card.spi_clock_changes(), [INIT_CLOCK_HZ, 25_000_000]);
+}
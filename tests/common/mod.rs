@@ -0,0 +1,46 @@
+//! Shared helpers of integration tests.
+
+#![allow(dead_code)]
+
+use sdmmc_spi::{
+    sim::{SimCard, SimClock},
+    DefaultSdMmcSpiConfig, SdMmcSpi, SpiDeviceTransport,
+};
+
+/// Discards defmt logs of the driver on the host.
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("");
+
+/// Block size.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Driver on top of a simulated card.
+pub type Sd<'a, Card> = SdMmcSpi<SpiDeviceTransport<&'a mut Card>, SimClock, DefaultSdMmcSpiConfig>;
+
+/// Driver on top of a simulated card, delays and transferred bytes advance the clock
+/// of the card.
+pub fn device(card: &mut SimCard) -> Sd<'_, SimCard> {
+    let clock = card.clock();
+    let transport = SpiDeviceTransport::new(card).with_clock_control(set_spi_clock);
+
+    Sd::with_transport(transport, clock)
+}
+
+/// Clock control of a simulated card.
+pub fn set_spi_clock(card: &mut &mut SimCard, hz: u32) {
+    card.set_spi_clock(hz);
+}
+
+/// Block filled with pattern derived from the block index.
+pub fn pattern(block: usize) -> [u8; BLOCK_SIZE] {
+    core::array::from_fn(|i| (i + block * 7) as u8)
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use common::{pattern, BLOCK_SIZE};
+use core::convert::Infallible;
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind, SimClock},
+    DiskioDevice, DiskioError, Error, SdMmcSpi, SdMmcSpiConfig, SpiDeviceTransport, Transport,
+};
+
+const BLOCK_COUNT: usize = 2048;
+const BLOCKS: usize = 4;
+
+struct NoRetries;
+
+impl SdMmcSpiConfig for NoRetries {
+    const TRANSFER_RETRIES: usize = 0;
+}
+
+/// Transport emulating DMA for block payloads, records buffers passed to the hooks.
+struct DmaTransport<'a> {
+    inner: SpiDeviceTransport<&'a mut SimCard>,
+    received: Vec<(*const u8, usize)>,
+    sent: Vec<(*const u8, usize)>,
+    corrupt_received: bool,
+    corrupt_sent: bool,
+}
+
+impl Transport for DmaTransport<'_> {
+    type Error = Infallible;
+    type SelectError = Infallible;
+
+    fn select(&mut self) -> Result<(), Self::SelectError> {
+        self.inner.select()
+    }
+
+    fn unselect(&mut self) -> Result<(), Self::SelectError> {
+        self.inner.unselect()
+    }
+
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.inner.transfer(data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write(data)
+    }
+
+    fn receive_block(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.received.push((data.as_ptr(), data.len()));
+        self.inner.receive_block(data)?;
+
+        if self.corrupt_received {
+            data[0] ^= 0x01;
+        }
+        Ok(())
+    }
+
+    fn send_block(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.sent.push((data.as_ptr(), data.len()));
+
+        if self.corrupt_sent {
+            let mut corrupted = data.to_vec();
+            corrupted[0] ^= 0x01;
+            self.inner.send_block(&corrupted)
+        } else {
+            self.inner.send_block(data)
+        }
+    }
+}
+
+type Driver<'a> = SdMmcSpi<DmaTransport<'a>, SimClock, NoRetries>;
+
+fn initialized(card: &mut SimCard) -> Driver<'_> {
+    let clock = card.clock();
+    let mut sd = Driver::with_transport(
+        DmaTransport {
+            inner: SpiDeviceTransport::new(card),
+            received: Vec::new(),
+            sent: Vec::new(),
+            corrupt_received: false,
+            corrupt_sent: false,
+        },
+        clock,
+    );
+    sd.initialize().unwrap();
+
+    let transport = sd.transport_mut();
+    transport.received.clear();
+    transport.sent.clear();
+    sd
+}
+
+fn blocks() -> Vec<u8> {
+    (0..BLOCKS).flat_map(pattern).collect()
+}
+
+/// Block payloads of the buffer, in order.
+fn block_ptrs(buf: &[u8]) -> Vec<(*const u8, usize)> {
+    buf.chunks(BLOCK_SIZE)
+        .map(|block| (block.as_ptr(), BLOCK_SIZE))
+        .collect()
+}
+
+#[test]
+fn hooks_use_caller_buffer() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let data = blocks();
+
+    sd.write(&data[..BLOCK_SIZE], 0).unwrap();
+    sd.write(&data, 8).unwrap();
+    let written = [block_ptrs(&data[..BLOCK_SIZE]), block_ptrs(&data)].concat();
+    assert_eq!(sd.transport_mut().sent, written);
+    sd.transport_mut().sent.clear();
+
+    let mut buf = vec![0; BLOCKS * BLOCK_SIZE];
+    sd.read(&mut buf, 8).unwrap();
+    assert_eq!(buf, data);
+
+    let transport = sd.transport_mut();
+    assert_eq!(transport.received, block_ptrs(&buf));
+    assert!(transport.sent.is_empty());
+    drop(sd);
+
+    for block in 0..BLOCKS {
+        assert_eq!(card.block(8 + block as u64), pattern(block));
+    }
+}
+
+#[test]
+fn read_crc_checked_after_hook() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    sd.write(&pattern(3), 3).unwrap();
+
+    sd.transport_mut().corrupt_received = true;
+    let mut buf = [0; BLOCK_SIZE];
+    assert!(matches!(
+        sd.read(&mut buf, 3),
+        Err(DiskioError::Hardware(Error::CrcError(_, _)))
+    ));
+    assert_eq!(sd.transport_mut().received.len(), 1);
+
+    sd.transport_mut().corrupt_received = false;
+    sd.read(&mut buf, 3).unwrap();
+    assert_eq!(buf, pattern(3));
+}
+
+#[test]
+fn write_crc_computed_from_caller_buffer() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    // The card sees the corrupted payload, the CRC is still of the caller's data.
+    sd.transport_mut().corrupt_sent = true;
+    assert!(matches!(
+        sd.write(&pattern(5), 5),
+        Err(DiskioError::Hardware(Error::WriteCrcError))
+    ));
+    assert_eq!(sd.transport_mut().sent.len(), 1);
+
+    sd.transport_mut().corrupt_sent = false;
+    sd.write(&pattern(5), 5).unwrap();
+    drop(sd);
+
+    assert_eq!(card.block(5), pattern(5));
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind},
+    DefaultSdMmcSpiConfig, DiskioDevice, DiskioError, Error, IoctlCmd, SdMmcSpi, SdMmcSpiConfig,
+    SpiDeviceTransport,
+};
+
+const BLOCK_COUNT: usize = 4096;
+const FILLED_BLOCKS: usize = 512;
+
+struct ShortEraseConfig;
+
+impl SdMmcSpiConfig for ShortEraseConfig {
+    const ERASE_TIMEOUT_MS: u32 = 10;
+    const ERASE_TIMEOUT_PER_SECTOR_MS: u32 = 10;
+}
+
+fn card(kind: SimCardKind) -> SimCard {
+    let mut card = SimCard::new(kind, BLOCK_COUNT);
+    for block in 0..FILLED_BLOCKS {
+        card.image_mut()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+            .copy_from_slice(&pattern(block));
+    }
+    card
+}
+
+fn assert_erased(card: &SimCard, erased: core::ops::RangeInclusive<usize>) {
+    for block in 0..FILLED_BLOCKS {
+        let data = &card.image()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE];
+        if erased.contains(&block) {
+            assert_eq!(data, [0; BLOCK_SIZE], "block {block}");
+        } else {
+            assert_eq!(data, pattern(block), "block {block}");
+        }
+    }
+}
+
+#[test]
+fn not_initialized() {
+    let mut card = card(SimCardKind::Sdhc);
+    let sd = device(&mut card);
+
+    assert!(matches!(sd.erase(0, 0), Err(DiskioError::NotInitialized)));
+}
+
+#[test]
+fn single_blocks() {
+    let mut card = card(SimCardKind::SdscV2);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert_eq!(sd.csd().unwrap().erase_unit_blocks(), 1);
+    sd.erase(3, 5).unwrap();
+    assert!(matches!(sd.erase(5, 3), Err(DiskioError::InvalidArgument)));
+
+    assert_erased(&card, 3..=5);
+}
+
+#[test]
+fn sectors() {
+    let mut card = card(SimCardKind::Sdhc).with_erase_single_block(false);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert_eq!(sd.csd().unwrap().erase_unit_blocks(), 128);
+    assert!(matches!(sd.erase(3, 5), Err(DiskioError::InvalidArgument)));
+    assert!(matches!(
+        sd.erase(128, 200),
+        Err(DiskioError::InvalidArgument)
+    ));
+    sd.erase(128, 255).unwrap();
+
+    assert_erased(&card, 128..=255);
+}
+
+#[test]
+fn trim_keeps_partial_sectors() {
+    let mut card = card(SimCardKind::SdscV1).with_erase_single_block(false);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    sd.ioctl(IoctlCmd::CtrlTrim(&(3, 5))).unwrap();
+    sd.ioctl(IoctlCmd::CtrlTrim(&(100, 400))).unwrap();
+
+    assert_erased(&card, 128..=383);
+}
+
+#[test]
+fn long_busy() {
+    let mut card = card(SimCardKind::Sdhc)
+        .with_erase_time(u64::from(DefaultSdMmcSpiConfig::WRITE_TIMEOUT_MS) * 1000 * 4);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    sd.erase(0, 7).unwrap();
+
+    assert_erased(&card, 0..=7);
+}
+
+#[test]
+fn busy_timeout() {
+    let mut card = card(SimCardKind::Sdhc).with_erase_time(20_000);
+    let clock = card.clock();
+    let mut sd = SdMmcSpi::<_, _, ShortEraseConfig>::with_transport(
+        SpiDeviceTransport::new(&mut card),
+        clock,
+    );
+    sd.initialize().unwrap();
+
+    assert!(matches!(
+        sd.erase(0, 7),
+        Err(DiskioError::Hardware(Error::TimeoutErase))
+    ));
+}
+
+#[test]
+fn timeout_scaled_by_sectors() {
+    // 128-block erase sectors, 10 ms per sector with 10 ms at least.
+    let mut card = card(SimCardKind::Sdhc).with_erase_time(25_000);
+    let clock = card.clock();
+    let mut sd = SdMmcSpi::<_, _, ShortEraseConfig>::with_transport(
+        SpiDeviceTransport::new(&mut card),
+        clock,
+    );
+    sd.initialize().unwrap();
+
+    assert!(matches!(
+        sd.erase(0, 255),
+        Err(DiskioError::Hardware(Error::TimeoutErase))
+    ));
+    sd.erase(0, 383).unwrap();
+    drop(sd);
+
+    assert_erased(&card, 0..=383);
+}
+
+#[test]
+fn timeout_from_sd_status_above_config() {
+    // 16 KiB AUs, erasing an AU takes up to 40 s, longer than the config fallback.
+    let mut card = card(SimCardKind::Sdhc)
+        .with_au_size(0x01)
+        .with_erase_timeout(1, 40, 0)
+        .with_erase_time(35_000_000);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert_eq!(sd.sd_status().unwrap().erase_timeout_ms(32), Some(40_000));
+    sd.erase(0, 31).unwrap();
+
+    assert_erased(&card, 0..=31);
+}
+
+#[test]
+fn timeout_from_sd_status() {
+    // 16 KiB AUs, erasing an AU takes up to 1 s.
+    let mut card = card(SimCardKind::Sdhc)
+        .with_au_size(0x01)
+        .with_erase_timeout(1, 1, 0)
+        .with_erase_time(1_500_000);
+    let clock = card.clock();
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert_eq!(sd.sd_status().unwrap().erase_timeout_ms(64), Some(2000));
+    sd.erase(0, 63).unwrap();
+
+    assert_eq!(sd.sd_status().unwrap().erase_timeout_ms(8), Some(1000));
+    let start = clock.now_us();
+    assert!(matches!(
+        sd.erase(64, 71),
+        Err(DiskioError::Hardware(Error::TimeoutErase))
+    ));
+    assert!(clock.now_us() - start < 1_500_000);
+
+    assert_erased(&card, 0..=71);
+}
@@ -0,0 +1,318 @@
+mod common;
+
+use common::{device, pattern, Sd, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{Fault, SimCard, SimCardKind},
+    DefaultSdMmcSpiConfig, DiskioDevice, DiskioError, Error, SdMmcSpiConfig, StatusFlag, R1, R2,
+};
+
+const BLOCK_COUNT: usize = 2048;
+const CMD13: u8 = 0x40 + 13;
+const CMD17: u8 = 0x40 + 17;
+
+fn initialized(card: &mut SimCard) -> Sd<'_, SimCard> {
+    let mut sd = device(card);
+    sd.initialize().unwrap();
+    sd
+}
+
+#[test]
+fn enter_spi_mode_retries() {
+    let attempts = DefaultSdMmcSpiConfig::ENTER_SPI_MODE_ATTEMPTS - 1;
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_fault(Fault::IgnoreCommand {
+        index: 0,
+        count: attempts,
+    });
+
+    initialized(&mut card);
+
+    let cmd0_count = card.commands().iter().filter(|cmd| cmd.index == 0).count();
+    assert_eq!(cmd0_count, attempts + 1);
+    assert_eq!(card.pending_faults(), 0);
+}
+
+#[test]
+fn enter_spi_mode_gives_up() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_fault(Fault::IgnoreCommand {
+        index: 0,
+        count: DefaultSdMmcSpiConfig::ENTER_SPI_MODE_ATTEMPTS,
+    });
+    let mut sd = device(&mut card);
+
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::Hardware(Error::CardNotFound))
+    ));
+    assert!(sd.status().contains(StatusFlag::ErrorOccured));
+    assert!(sd.status().contains(StatusFlag::NotInitialized));
+}
+
+#[test]
+fn corrupted_response_fails_init() {
+    let mut card =
+        SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_fault(Fault::CorruptResponse {
+            index: 59,
+            mask: 0x01,
+        });
+    let mut sd = device(&mut card);
+
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::Hardware(Error::CardNotFound))
+    ));
+}
+
+#[test]
+fn dropped_response_times_out() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let mut buf = [0; BLOCK_SIZE];
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::DropResponse { index: 17 });
+
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::Hardware(Error::TimeoutCommand(CMD17)))
+    ));
+}
+
+#[test]
+fn flipped_data_bit_fails_crc() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let mut buf = [0; BLOCK_SIZE];
+
+    for _ in 0..=DefaultSdMmcSpiConfig::TRANSFER_RETRIES {
+        sd.transport_mut()
+            .spi_mut()
+            .inject(Fault::FlipDataBit { bit: 100 });
+    }
+
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::Hardware(Error::CrcError(_, _)))
+    ));
+    sd.read(&mut buf, 0).unwrap();
+}
+
+#[test]
+fn stretched_busy_times_out() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    sd.transport_mut().spi_mut().inject(Fault::StretchBusy {
+        bytes: (DefaultSdMmcSpiConfig::WRITE_TIMEOUT_MS * 1000
+            / DefaultSdMmcSpiConfig::POLL_DELAY_US) as usize
+            + 1,
+    });
+
+    assert!(matches!(
+        sd.write(&pattern(0), 0),
+        Err(DiskioError::Hardware(Error::TimeoutWaitAvailable))
+    ));
+}
+
+#[test]
+fn rejected_write() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::RejectWrite { response: 0x0D });
+
+    assert!(matches!(
+        sd.write(&pattern(1), 1),
+        Err(DiskioError::Hardware(Error::WriteError))
+    ));
+    assert_eq!(card.image()[BLOCK_SIZE..2 * BLOCK_SIZE], [0; BLOCK_SIZE]);
+}
+
+#[test]
+fn unknown_data_response() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::RejectWrite { response: 0x07 });
+
+    assert!(matches!(
+        sd.write(&pattern(1), 1),
+        Err(DiskioError::Hardware(Error::UnknownDataResponse(0x07)))
+    ));
+}
+
+#[test]
+fn partial_read() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    for block in BLOCK_COUNT - 2..BLOCK_COUNT {
+        card.image_mut()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+            .copy_from_slice(&pattern(block));
+    }
+    let sd = initialized(&mut card);
+    let mut buf = [0; BLOCK_SIZE * 4];
+
+    let err = sd
+        .read_blocks(&mut buf, BLOCK_COUNT as u64 - 2)
+        .unwrap_err();
+    assert_eq!(err.blocks, 2);
+    assert!(matches!(
+        err.error,
+        DiskioError::Hardware(Error::OutOfRange)
+    ));
+    assert_eq!(buf[..BLOCK_SIZE], pattern(BLOCK_COUNT - 2));
+    assert_eq!(buf[BLOCK_SIZE..2 * BLOCK_SIZE], pattern(BLOCK_COUNT - 1));
+
+    sd.read_blocks(&mut buf, 0).unwrap();
+}
+
+#[test]
+fn partial_write() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let sd = initialized(&mut card);
+    let blocks: Vec<u8> = (0..4).flat_map(pattern).collect();
+
+    let err = sd
+        .write_blocks(&blocks, BLOCK_COUNT as u64 - 2)
+        .unwrap_err();
+    assert_eq!(err.blocks, 2);
+    assert!(matches!(
+        err.error,
+        DiskioError::Hardware(Error::WriteError)
+    ));
+
+    sd.write_blocks(&blocks, 0).unwrap();
+
+    assert_eq!(card.image()[..4 * BLOCK_SIZE], blocks[..]);
+    assert_eq!(
+        card.image()[(BLOCK_COUNT - 2) * BLOCK_SIZE..],
+        blocks[..2 * BLOCK_SIZE]
+    );
+}
+
+#[test]
+fn rejected_first_block() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let blocks: Vec<u8> = (0..4).flat_map(pattern).collect();
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::RejectWrite { response: 0x0D });
+
+    let err = sd.write_blocks(&blocks, 0).unwrap_err();
+    assert_eq!(err.blocks, 0);
+
+    sd.write_blocks(&blocks, 8).unwrap();
+}
+
+#[test]
+fn oversized_written_blocks() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let blocks: Vec<u8> = (0..4).flat_map(pattern).collect();
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::WrittenBlocks { count: 100 });
+
+    let err = sd
+        .write_blocks(&blocks, BLOCK_COUNT as u64 - 2)
+        .unwrap_err();
+    assert_eq!(err.blocks, 2);
+    assert!(matches!(
+        err.error,
+        DiskioError::Hardware(Error::WriteError)
+    ));
+
+    // The retry starts from the first block instead of past the end of the buffer.
+    let spi = sd.transport_mut().spi_mut();
+    spi.inject(Fault::RejectWrite { response: 0x0B });
+    spi.inject(Fault::WrittenBlocks { count: u32::MAX });
+
+    sd.write_blocks(&blocks, 0).unwrap();
+    drop(sd);
+
+    assert_eq!(card.image()[..4 * BLOCK_SIZE], blocks[..]);
+}
+
+#[test]
+fn data_error_tokens() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+    let mut buf = [0; BLOCK_SIZE * 2];
+
+    for (token, expected) in [
+        (0x01, Error::CardError),
+        (0x02, Error::CardControllerError),
+        (0x04, Error::CardEccFailed),
+        (0x08, Error::OutOfRange),
+        (0x0D, Error::OutOfRange),
+        (0x40, Error::ReadError),
+    ] {
+        sd.transport_mut()
+            .spi_mut()
+            .inject(Fault::DataErrorToken { token });
+
+        match sd.read(&mut buf, 0) {
+            Err(DiskioError::Hardware(err)) => assert_eq!(err, expected, "token 0x{token:02X}"),
+            _ => panic!("token 0x{token:02X} is not reported"),
+        }
+        sd.read(&mut buf, 0).unwrap();
+    }
+}
+
+#[test]
+fn rejected_write_crc() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    for _ in 0..=DefaultSdMmcSpiConfig::TRANSFER_RETRIES {
+        sd.transport_mut()
+            .spi_mut()
+            .inject(Fault::RejectWrite { response: 0x0B });
+    }
+
+    assert!(matches!(
+        sd.write(&pattern(1), 1),
+        Err(DiskioError::Hardware(Error::WriteCrcError))
+    ));
+    sd.write(&pattern(1), 1).unwrap();
+}
+
+#[test]
+fn write_status_error() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = initialized(&mut card);
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::StatusError { bits: 0x20 });
+
+    match sd.write(&pattern(1), 1) {
+        Err(DiskioError::Hardware(Error::ErrorCommand(CMD13, status))) => {
+            assert_eq!(status, R2::WP_VIOLATION);
+            assert_eq!(status.r1(), R1::READY_STATE);
+        }
+        _ => panic!("status error is not reported"),
+    }
+}
+
+#[test]
+fn read_out_of_range_address() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let sd = initialized(&mut card);
+    let mut buf = [0; BLOCK_SIZE];
+
+    match sd.read(&mut buf, BLOCK_COUNT as u64) {
+        Err(DiskioError::Hardware(Error::ErrorCommand(CMD17, status))) => {
+            assert_eq!(status, R2::PARAMETER_ERROR);
+            assert_eq!(status.r1(), R1::PARAMETER_ERROR);
+        }
+        _ => panic!("parameter error is not reported"),
+    }
+    sd.read(&mut buf, 0).unwrap();
+}
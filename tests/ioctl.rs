@@ -0,0 +1,98 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind},
+    DiskioDevice, DiskioError, IoctlCmd,
+};
+
+const BLOCK_COUNT: usize = 4096;
+
+fn card(kind: SimCardKind) -> SimCard {
+    let mut card = SimCard::new(kind, BLOCK_COUNT);
+    for block in 0..16 {
+        card.image_mut()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+            .copy_from_slice(&pattern(block));
+    }
+    card
+}
+
+#[test]
+fn not_initialized() {
+    let mut card = card(SimCardKind::Sdhc);
+    let sd = device(&mut card);
+    let mut sector_count = 0;
+
+    assert!(matches!(
+        sd.ioctl(IoctlCmd::GetSectorCount(&mut sector_count)),
+        Err(DiskioError::NotInitialized)
+    ));
+}
+
+#[test]
+fn geometry() {
+    let mut card = card(SimCardKind::Sdhc);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let mut sector_count = 0;
+    let mut sector_size = 0;
+    let mut block_size = 0;
+    sd.ioctl(IoctlCmd::GetSectorCount(&mut sector_count))
+        .unwrap();
+    sd.ioctl(IoctlCmd::GetSectorSize(&mut sector_size)).unwrap();
+    sd.ioctl(IoctlCmd::GetBlockSize(&mut block_size)).unwrap();
+    sd.ioctl(IoctlCmd::CtrlSync).unwrap();
+
+    assert_eq!(sector_count, BLOCK_COUNT as u64);
+    assert_eq!(sector_size, BLOCK_SIZE);
+    assert_eq!(block_size, 128);
+}
+
+#[test]
+fn block_size_from_au_size() {
+    let mut card = card(SimCardKind::Sdhc).with_au_size(0x09);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let mut block_size = 0;
+    sd.ioctl(IoctlCmd::GetBlockSize(&mut block_size)).unwrap();
+
+    assert_eq!(sd.sd_status().unwrap().au_size_blocks(), 8192);
+    assert_eq!(block_size, 8192);
+}
+
+fn trim(kind: SimCardKind) {
+    let mut card = card(kind);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    sd.ioctl(IoctlCmd::CtrlTrim(&(4, 7))).unwrap();
+    assert!(matches!(
+        sd.ioctl(IoctlCmd::CtrlTrim(&(7, 4))),
+        Err(DiskioError::InvalidArgument)
+    ));
+    assert!(matches!(
+        sd.ioctl(IoctlCmd::CtrlTrim(&(0, BLOCK_COUNT as u64))),
+        Err(DiskioError::InvalidArgument)
+    ));
+
+    for block in 0..16 {
+        let data = &card.image()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE];
+        if (4..=7).contains(&block) {
+            assert_eq!(data, [0; BLOCK_SIZE]);
+        } else {
+            assert_eq!(data, pattern(block));
+        }
+    }
+}
+
+#[test]
+fn trim_sdsc() {
+    trim(SimCardKind::SdscV2);
+}
+
+#[test]
+fn trim_sdhc() {
+    trim(SimCardKind::Sdhc);
+}
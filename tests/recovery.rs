@@ -0,0 +1,129 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{Fault, SimCard, SimCardKind, SimClock},
+    DiskioDevice, DiskioError, Error, SdMmcSpi, SdMmcSpiConfig, SpiDeviceTransport, StatusFlag,
+};
+
+const BLOCK_COUNT: usize = 2048;
+const CMD17: u8 = 0x40 + 17;
+
+struct RecoveryConfig;
+
+impl SdMmcSpiConfig for RecoveryConfig {
+    const RECOVERY_ATTEMPTS: usize = 2;
+}
+
+type RecoverySd<'a> = SdMmcSpi<SpiDeviceTransport<&'a mut SimCard>, SimClock, RecoveryConfig>;
+
+fn card() -> SimCard {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    card.image_mut()[..BLOCK_SIZE].copy_from_slice(&pattern(0));
+    card
+}
+
+fn cmd0_count(card: &SimCard) -> usize {
+    card.commands().iter().filter(|cmd| cmd.index == 0).count()
+}
+
+#[test]
+fn lost_card_sets_error() {
+    let mut card = card();
+    let mut sd = device(&mut card);
+    let mut buf = [0; BLOCK_SIZE];
+    sd.initialize().unwrap();
+
+    sd.transport_mut().spi_mut().power_cycle();
+
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::Hardware(Error::TimeoutCommand(CMD17)))
+    ));
+    assert!(sd.status().contains(StatusFlag::ErrorOccured));
+
+    sd.reset();
+    sd.initialize().unwrap();
+    sd.read(&mut buf, 0).unwrap();
+
+    assert_eq!(buf, pattern(0));
+    assert!(sd.status().is_empty());
+}
+
+#[test]
+fn recovers_lost_card() {
+    let mut card = card();
+    let clock = card.clock();
+    let mut sd = RecoverySd::with_transport(SpiDeviceTransport::new(&mut card), clock);
+    let mut buf = [0; BLOCK_SIZE * 2];
+    sd.initialize().unwrap();
+
+    sd.transport_mut().spi_mut().power_cycle();
+    sd.read(&mut buf[..BLOCK_SIZE], 0).unwrap();
+    assert_eq!(buf[..BLOCK_SIZE], pattern(0));
+    assert!(sd.status().is_empty());
+
+    let blocks: Vec<u8> = (1..3).flat_map(pattern).collect();
+    sd.transport_mut().spi_mut().power_cycle();
+    sd.write(&blocks, 1).unwrap();
+    sd.read(&mut buf, 1).unwrap();
+    assert_eq!(buf[..], blocks[..]);
+
+    assert_eq!(cmd0_count(sd.transport_mut().spi_mut()), 3);
+}
+
+#[test]
+fn gives_up_on_missing_card() {
+    let mut card = card();
+    let clock = card.clock();
+    let mut sd = RecoverySd::with_transport(SpiDeviceTransport::new(&mut card), clock);
+    let mut buf = [0; BLOCK_SIZE];
+    sd.initialize().unwrap();
+
+    **sd.transport_mut().spi_mut() = card_without_spi_mode();
+
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::Hardware(Error::CardNotFound))
+    ));
+    assert!(sd.status().contains(StatusFlag::ErrorOccured));
+    assert!(sd.status().contains(StatusFlag::NotInitialized));
+
+    let cmd17_count = |card: &SimCard| card.commands().iter().filter(|c| c.index == 17).count();
+    let reads = cmd17_count(sd.transport_mut().spi_mut());
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::NotInitialized)
+    ));
+    assert_eq!(cmd17_count(sd.transport_mut().spi_mut()), reads);
+}
+
+#[test]
+fn changed_card() {
+    let mut card = card();
+    let clock = card.clock();
+    let mut sd = RecoverySd::with_transport(SpiDeviceTransport::new(&mut card), clock);
+    let mut buf = [0; BLOCK_SIZE];
+    sd.initialize().unwrap();
+
+    **sd.transport_mut().spi_mut() =
+        SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_serial_number(0xCAFE_F00D);
+
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::Hardware(Error::CardChanged))
+    ));
+    assert!(sd.status().contains(StatusFlag::ErrorOccured));
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::NotInitialized)
+    ));
+}
+
+/// Card which never answers CMD0.
+fn card_without_spi_mode() -> SimCard {
+    SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_fault(Fault::IgnoreCommand {
+        index: 0,
+        count: usize::MAX,
+    })
+}
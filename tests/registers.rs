@@ -0,0 +1,296 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind},
+    CardType, Csd, DiskioDevice, DiskioError, Error, Ocr, Scr, SdMmcSpi, SdMmcSpiConfig,
+    SdSpecVersion, SdStatus, Size, SpiDeviceTransport,
+};
+
+const BLOCK_COUNT: usize = 2048;
+
+#[test]
+fn cid() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_serial_number(0xCAFE_0001);
+    let mut sd = device(&mut card);
+
+    assert!(sd.cid().is_none());
+    sd.initialize().unwrap();
+
+    let cid = sd.cid().unwrap();
+    assert_eq!(cid.manufacturer_id(), SimCard::MANUFACTURER_ID);
+    assert_eq!(&cid.oem_id(), b"SM");
+    assert_eq!(&cid.product_name(), b"SIMSD");
+    assert_eq!(cid.product_revision_major(), 1);
+    assert_eq!(cid.product_revision_minor(), 0);
+    assert_eq!(cid.serial_number(), 0xCAFE_0001);
+    assert_eq!(cid.manufacturing_year(), 2023);
+    assert_eq!(cid.manufacturing_month(), 6);
+}
+
+#[test]
+fn cid_detects_card_swap() {
+    let mut first = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = device(&mut first);
+    sd.initialize().unwrap();
+    let first_cid = *sd.cid().unwrap();
+
+    let mut second = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_serial_number(1);
+    let mut sd = device(&mut second);
+    sd.initialize().unwrap();
+
+    assert_ne!(first_cid, *sd.cid().unwrap());
+}
+
+fn capacity(kind: SimCardKind, block_count: usize) -> (CardType, Csd, Size, u64) {
+    let mut card = SimCard::new(kind, block_count);
+    let mut sd = device(&mut card);
+
+    assert!(sd.card_type().is_none());
+    assert!(sd.csd().is_none());
+    assert!(sd.card_capacity().is_none());
+    sd.initialize().unwrap();
+
+    (
+        sd.card_type().unwrap(),
+        *sd.csd().unwrap(),
+        sd.card_capacity().unwrap(),
+        sd.card_capacity_blocks().unwrap(),
+    )
+}
+
+#[test]
+fn capacity_sdsc_v1() {
+    let (card_type, csd, size, blocks) = capacity(SimCardKind::SdscV1, 4096);
+
+    assert_eq!(card_type, CardType::SD1);
+    assert!(matches!(csd, Csd::V1(_)));
+    assert_eq!(size, Size::from_bytes(4096 * 512));
+    assert_eq!(blocks, 4096);
+}
+
+#[test]
+fn capacity_sdsc_v2() {
+    let (card_type, csd, size, blocks) = capacity(SimCardKind::SdscV2, 6144);
+
+    assert_eq!(card_type, CardType::SD2);
+    assert!(matches!(csd, Csd::V1(_)));
+    assert_eq!(size, Size::from_bytes(6144 * 512));
+    assert_eq!(blocks, 6144);
+}
+
+#[test]
+fn capacity_sdhc() {
+    let (card_type, csd, size, blocks) = capacity(SimCardKind::Sdhc, 3072);
+
+    assert_eq!(card_type, CardType::SDHC);
+    assert!(matches!(csd, Csd::V2(_)));
+    assert_eq!(size, Size::from_bytes(3072 * 512));
+    assert_eq!(blocks, 3072);
+}
+
+fn capacity_sparse(kind: SimCardKind, block_count: u64) -> (CardType, Csd, u64) {
+    let mut card = SimCard::sparse(kind, block_count);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    (
+        sd.card_type().unwrap(),
+        *sd.csd().unwrap(),
+        sd.card_capacity_blocks().unwrap(),
+    )
+}
+
+#[test]
+fn capacity_sdxc() {
+    // 64 GiB.
+    let (card_type, csd, blocks) = capacity_sparse(SimCardKind::Sdxc, 0x800_0000);
+
+    assert_eq!(card_type, CardType::SDXC);
+    assert!(matches!(csd, Csd::V2(_)));
+    assert_eq!(blocks, 0x800_0000);
+}
+
+#[test]
+fn capacity_sduc() {
+    // 4 TiB.
+    let (card_type, csd, blocks) = capacity_sparse(SimCardKind::Sduc, 0x2_0000_0000);
+
+    assert_eq!(card_type, CardType::SDUC);
+    assert!(matches!(csd, Csd::V3(_)));
+    assert_eq!(blocks, 0x2_0000_0000);
+}
+
+fn scr(kind: SimCardKind) -> Scr {
+    let mut card = SimCard::new(kind, BLOCK_COUNT);
+    let mut sd = device(&mut card);
+
+    assert!(sd.scr().is_none());
+    sd.initialize().unwrap();
+
+    *sd.scr().unwrap()
+}
+
+#[test]
+fn scr_spec_version() {
+    assert_eq!(scr(SimCardKind::SdscV1).spec_version(), SdSpecVersion::V1_0);
+    assert_eq!(scr(SimCardKind::SdscV2).spec_version(), SdSpecVersion::V2);
+    assert_eq!(scr(SimCardKind::Sdhc).spec_version(), SdSpecVersion::V3);
+
+    let scr = scr(SimCardKind::Sdhc);
+    assert_eq!(scr.structure(), 0);
+    assert_eq!(scr.sd_bus_widths(), 0x05);
+    assert!(scr.cmd23_supported());
+    assert_eq!(scr.erased_byte(), 0x00);
+}
+
+#[test]
+fn scr_set_block_count() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+    sd.write(&[pattern(4), pattern(5), pattern(6)].concat(), 4)
+        .unwrap();
+    drop(sd);
+    card.clear_commands();
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+    let mut buf = [0; 3 * BLOCK_SIZE];
+    sd.read(&mut buf, 4).unwrap();
+    assert_eq!(buf, [pattern(4), pattern(5), pattern(6)].concat()[..]);
+
+    let read: Vec<_> = card
+        .commands()
+        .iter()
+        .skip_while(|command| command.index != 23 || command.app)
+        .map(|command| (command.index, command.arg))
+        .collect();
+    assert_eq!(read, [(23, 3), (18, 4)]);
+}
+
+#[test]
+fn scr_no_set_block_count() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_cmd23(false);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert!(!sd.scr().unwrap().cmd23_supported());
+    let mut buf = [0; 3 * BLOCK_SIZE];
+    sd.read(&mut buf, 4).unwrap();
+
+    let commands = card.commands();
+    assert!(!commands.iter().any(|c| c.index == 23 && !c.app));
+    assert_eq!(commands.last().unwrap().index, 12);
+}
+
+#[test]
+fn scr_erased_byte() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_erased_byte(0xFF);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+    sd.write(&pattern(1), 1).unwrap();
+
+    let erased_byte = sd.scr().unwrap().erased_byte();
+    assert_eq!(erased_byte, 0xFF);
+
+    sd.erase(1, 1).unwrap();
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, 1).unwrap();
+    assert_eq!(buf, [erased_byte; BLOCK_SIZE]);
+}
+
+fn sd_status(kind: SimCardKind) -> SdStatus {
+    let mut card = SimCard::new(kind, BLOCK_COUNT)
+        .with_au_size(0x0A)
+        .with_erase_timeout(4, 10, 1);
+    let mut sd = device(&mut card);
+
+    assert!(sd.sd_status().is_none());
+    sd.initialize().unwrap();
+
+    *sd.sd_status().unwrap()
+}
+
+#[test]
+fn sd_status_sdhc() {
+    let status = sd_status(SimCardKind::Sdhc);
+
+    assert_eq!(status.speed_class(), 10);
+    assert_eq!(status.uhs_speed_grade(), 1);
+    assert_eq!(status.video_speed_class(), 10);
+    assert_eq!(status.protected_area_size(), SimCard::PROTECTED_AREA_SIZE);
+    assert_eq!(status.au_size_blocks(), 16384);
+    assert_eq!(status.erase_size(), 4);
+    assert_eq!(status.erase_timeout_s(), 10);
+    assert_eq!(status.erase_offset_s(), 1);
+    assert_eq!(status.erase_timeout_ms(1), Some(3500));
+    assert_eq!(status.erase_timeout_ms(16384 * 8), Some(21_000));
+}
+
+#[test]
+fn sd_status_sdsc() {
+    assert_eq!(sd_status(SimCardKind::SdscV2).speed_class(), 2);
+
+    let status = sd_status(SimCardKind::SdscV1);
+    assert_eq!(status.speed_class(), 0);
+    assert_eq!(status.uhs_speed_grade(), 0);
+    assert_eq!(status.video_speed_class(), 0);
+    assert_eq!(SdStatus::default().erase_timeout_ms(1), None);
+}
+
+struct LowVoltageConfig;
+
+impl SdMmcSpiConfig for LowVoltageConfig {
+    const SUPPLY_VOLTAGE_MV: u16 = 2800;
+}
+
+fn ocr(kind: SimCardKind) -> Ocr {
+    let mut card = SimCard::new(kind, BLOCK_COUNT);
+    let mut sd = device(&mut card);
+
+    assert!(sd.ocr().is_none());
+    sd.initialize().unwrap();
+
+    *sd.ocr().unwrap()
+}
+
+#[test]
+fn ocr_register() {
+    let sdhc = ocr(SimCardKind::Sdhc);
+
+    assert!(sdhc.power_up_status());
+    assert!(sdhc.card_capacity_status());
+    assert!(!sdhc.uhs2_card_status());
+    assert!(!sdhc.switching_to_18v_accepted());
+    assert_eq!(sdhc.voltage_window(), 0x1FF);
+    assert!(sdhc.supports_voltage_mv(2700));
+    assert!(sdhc.supports_voltage_mv(3600));
+    assert!(!sdhc.supports_voltage_mv(1800));
+
+    let sdsc = ocr(SimCardKind::SdscV2);
+    assert!(sdsc.power_up_status());
+    assert!(!sdsc.card_capacity_status());
+}
+
+#[test]
+fn ocr_unsupported_voltage() {
+    // 2.7-2.9V only.
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_voltage_window(0x003);
+    let mut sd = device(&mut card);
+
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::Hardware(Error::UnsupportedVoltage))
+    ));
+    assert!(!card.commands().iter().any(|c| c.app && c.index == 41));
+
+    let clock = card.clock();
+    let mut sd = SdMmcSpi::<_, _, LowVoltageConfig>::with_transport(
+        SpiDeviceTransport::new(&mut card),
+        clock,
+    );
+    sd.initialize().unwrap();
+
+    assert_eq!(sd.ocr().unwrap().voltage_window(), 0x003);
+}
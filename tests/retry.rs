@@ -0,0 +1,176 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{Fault, SimCard, SimCardKind, SimClock},
+    DiskioDevice, DiskioError, Error, SdMmcSpi, SdMmcSpiConfig, SpiDeviceTransport,
+};
+
+const BLOCK_COUNT: usize = 2048;
+const BLOCKS: usize = 64;
+const CHUNK_BLOCKS: usize = 8;
+const SEED: u64 = 0x5D_CA4D;
+const CMD12: u8 = 0x40 + 12;
+
+macro_rules! config {
+    ($name:ident, $retries:expr, $from_failed_block:expr) => {
+        struct $name;
+
+        impl SdMmcSpiConfig for $name {
+            const TRANSFER_RETRIES: usize = $retries;
+            const RETRY_FROM_FAILED_BLOCK: bool = $from_failed_block;
+        }
+    };
+}
+
+config!(NoRetries, 0, true);
+config!(ResumeRetries, 16, true);
+config!(RestartRetries, 32, false);
+
+type Driver<'a, Config> = SdMmcSpi<SpiDeviceTransport<&'a mut SimCard>, SimClock, Config>;
+
+fn noisy_card() -> SimCard {
+    SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_noise(SEED, 16)
+}
+
+fn blocks() -> Vec<u8> {
+    (0..BLOCKS).flat_map(pattern).collect()
+}
+
+fn write_read<Config: SdMmcSpiConfig>(
+    sd: &Driver<'_, Config>,
+) -> Result<Vec<u8>, DiskioError<Error<core::convert::Infallible, core::convert::Infallible>>> {
+    let data = blocks();
+    let mut buf = vec![0; data.len()];
+
+    for (i, chunk) in data.chunks(CHUNK_BLOCKS * BLOCK_SIZE).enumerate() {
+        sd.write(chunk, (i * CHUNK_BLOCKS) as u64)?;
+    }
+    for (i, chunk) in buf.chunks_mut(CHUNK_BLOCKS * BLOCK_SIZE).enumerate() {
+        sd.read(chunk, (i * CHUNK_BLOCKS) as u64)?;
+    }
+
+    Ok(buf)
+}
+
+fn check<Config: SdMmcSpiConfig>() {
+    let mut card = noisy_card();
+    let clock = card.clock();
+    let mut sd = Driver::<Config>::with_transport(SpiDeviceTransport::new(&mut card), clock);
+    sd.initialize().unwrap();
+
+    assert_eq!(write_read(&sd).unwrap(), blocks());
+    assert_eq!(card.image()[..BLOCKS * BLOCK_SIZE], blocks()[..]);
+
+    let starts: Vec<u32> = card
+        .commands()
+        .iter()
+        .filter(|cmd| !cmd.app && [17, 18, 24, 25].contains(&cmd.index))
+        .map(|cmd| cmd.arg)
+        .collect();
+    let restarts = starts.len() - 2 * BLOCKS / CHUNK_BLOCKS;
+    let resumes = starts
+        .iter()
+        .filter(|&&start| !(start as usize).is_multiple_of(CHUNK_BLOCKS))
+        .count();
+
+    assert!(restarts > 0);
+    if Config::RETRY_FROM_FAILED_BLOCK {
+        assert!(resumes > 0);
+    } else {
+        assert_eq!(resumes, 0);
+    }
+}
+
+#[test]
+fn noise_fails_without_retries() {
+    let mut card = noisy_card();
+    let clock = card.clock();
+    let mut sd = Driver::<NoRetries>::with_transport(SpiDeviceTransport::new(&mut card), clock);
+    sd.initialize().unwrap();
+
+    assert!(matches!(
+        write_read(&sd),
+        Err(DiskioError::Hardware(
+            Error::CrcError(_, _) | Error::WriteCrcError
+        ))
+    ));
+}
+
+#[test]
+fn retry_from_failed_block() {
+    check::<ResumeRetries>();
+}
+
+#[test]
+fn retry_from_first_block() {
+    check::<RestartRetries>();
+}
+
+#[test]
+fn not_retryable() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::RejectWrite { response: 0x0D });
+
+    assert!(matches!(
+        sd.write(&pattern(0), 0),
+        Err(DiskioError::Hardware(Error::WriteError))
+    ));
+    let writes = sd
+        .transport_mut()
+        .spi_mut()
+        .commands()
+        .iter()
+        .filter(|cmd| !cmd.app && cmd.index == 24)
+        .count();
+    assert_eq!(writes, 1);
+}
+
+struct RetryTimeouts;
+
+impl SdMmcSpiConfig for RetryTimeouts {
+    fn is_retryable<T, S>(error: &Error<T, S>) -> bool {
+        matches!(error, Error::TimeoutCommand(_))
+    }
+
+    fn is_card_lost<T, S>(_error: &Error<T, S>) -> bool {
+        false
+    }
+}
+
+#[test]
+fn stop_failure_after_last_block_not_retried() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_cmd23(false);
+    let clock = card.clock();
+    let mut sd = Driver::<RetryTimeouts>::with_transport(SpiDeviceTransport::new(&mut card), clock);
+    sd.initialize().unwrap();
+    sd.write(&blocks()[..CHUNK_BLOCKS * BLOCK_SIZE], 0).unwrap();
+
+    sd.transport_mut()
+        .spi_mut()
+        .inject(Fault::DropResponse { index: 12 });
+    sd.transport_mut().spi_mut().clear_commands();
+
+    let mut buf = vec![0; CHUNK_BLOCKS * BLOCK_SIZE];
+    let err = sd.read_blocks(&mut buf, 0).unwrap_err();
+    assert_eq!(err.blocks, CHUNK_BLOCKS);
+    assert!(matches!(
+        err.error,
+        DiskioError::Hardware(Error::TimeoutCommand(CMD12))
+    ));
+    assert_eq!(buf, blocks()[..CHUNK_BLOCKS * BLOCK_SIZE]);
+
+    let reads = sd
+        .transport_mut()
+        .spi_mut()
+        .commands()
+        .iter()
+        .filter(|cmd| cmd.index == 18)
+        .count();
+    assert_eq!(reads, 1);
+}
@@ -0,0 +1,243 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{Fault, SimCard, SimCardKind, SimCommand},
+    DiskioDevice, DiskioError, Error, StatusFlag, R1, R7,
+};
+
+const BLOCK_COUNT: usize = 2048;
+
+fn app_commands(card: &SimCard, index: u8) -> Vec<SimCommand> {
+    card.commands()
+        .iter()
+        .filter(|cmd| cmd.app && cmd.index == index)
+        .copied()
+        .collect()
+}
+
+fn has_command(card: &SimCard, index: u8) -> bool {
+    card.commands()
+        .iter()
+        .any(|cmd| !cmd.app && cmd.index == index)
+}
+
+#[test]
+fn init_sdsc_v1() {
+    let mut card = SimCard::new(SimCardKind::SdscV1, BLOCK_COUNT);
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+    assert!(sd.status().is_empty());
+
+    assert_eq!(app_commands(&card, 41)[0].arg, 0x0000_0000);
+    assert!(has_command(&card, 58));
+    assert!(has_command(&card, 9));
+}
+
+#[test]
+fn init_sdsc_v2() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT);
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert_eq!(app_commands(&card, 41)[0].arg, 0x4800_0000);
+    assert!(has_command(&card, 58));
+    assert!(has_command(&card, 9));
+}
+
+#[test]
+fn init_sdsc_capacity_overflow() {
+    // C_SIZE shifted by C_SIZE_MULT and READ_BL_LEN overflows the 16-bit field type.
+    let mut card = SimCard::new(SimCardKind::SdscV2, 4096);
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+    assert!(sd.status().is_empty());
+}
+
+#[test]
+fn init_sdhc_waits_for_ready() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_init_polls(5);
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert_eq!(app_commands(&card, 41).len(), 6);
+    assert!(has_command(&card, 58));
+}
+
+#[test]
+fn init_check_pattern_mismatch() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT)
+        .with_fault(Fault::CorruptCheckPattern { mask: 0x01 });
+
+    let mut sd = device(&mut card);
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::Hardware(Error::CheckPatternMismatch))
+    ));
+
+    assert_eq!(
+        card.commands().iter().filter(|cmd| cmd.index == 8).count(),
+        1
+    );
+    assert!(app_commands(&card, 41).is_empty());
+}
+
+#[test]
+fn init_voltage_not_accepted() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT).with_voltage_window(0);
+
+    let mut sd = device(&mut card);
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::Hardware(Error::UnsupportedVoltage))
+    ));
+
+    assert!(!has_command(&card, 58));
+    assert!(app_commands(&card, 41).is_empty());
+}
+
+#[test]
+fn r7() {
+    let r7 = R7::new(R1::IN_IDLE_STATE, [0x10, 0x00, 0x01, 0xAA]);
+
+    assert_eq!(r7.r1, R1::IN_IDLE_STATE);
+    assert_eq!(r7.command_version(), 1);
+    assert_eq!(r7.voltage_accepted(), R7::VOLTAGE_2V7_3V6);
+    assert_eq!(r7.check_pattern(), 0xAA);
+    assert_eq!(r7.result::<(), ()>(), Ok(()));
+    assert_eq!(
+        R7::new(R1::IN_IDLE_STATE, [0x00, 0x00, 0x01, 0xAB]).result::<(), ()>(),
+        Err(Error::CheckPatternMismatch)
+    );
+    assert_eq!(
+        R7::new(R1::IN_IDLE_STATE, [0x00, 0x00, 0x00, 0xAA]).result::<(), ()>(),
+        Err(Error::UnsupportedVoltage)
+    );
+}
+
+#[test]
+fn initialize_twice() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::AlreadyInitialized)
+    ));
+}
+
+#[test]
+fn read_before_initialize() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let sd = device(&mut card);
+    let mut buf = [0; BLOCK_SIZE];
+
+    assert!(sd.status().contains(StatusFlag::NotInitialized));
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::NotInitialized)
+    ));
+}
+
+#[test]
+fn invalid_buffer_length() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+    let mut buf = [0; BLOCK_SIZE + 1];
+
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::InvalidArgument)
+    ));
+    assert!(matches!(
+        sd.write(&buf[..0], 0),
+        Err(DiskioError::InvalidArgument)
+    ));
+}
+
+fn read_write(kind: SimCardKind, address_unit: u32) {
+    let mut card = SimCard::new(kind, BLOCK_COUNT);
+    for block in 0..8 {
+        card.image_mut()[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+            .copy_from_slice(&pattern(block));
+    }
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let mut buf = [0; BLOCK_SIZE * 4];
+    sd.read(&mut buf[..BLOCK_SIZE], 3).unwrap();
+    assert_eq!(buf[..BLOCK_SIZE], pattern(3));
+
+    sd.read(&mut buf, 2).unwrap();
+    for (i, chunk) in buf.chunks(BLOCK_SIZE).enumerate() {
+        assert_eq!(chunk, pattern(2 + i));
+    }
+
+    sd.write(&pattern(100), 10).unwrap();
+    let blocks: Vec<u8> = (200..203).flat_map(pattern).collect();
+    sd.write(&blocks, 20).unwrap();
+
+    assert_eq!(card.image()[10 * BLOCK_SIZE..11 * BLOCK_SIZE], pattern(100));
+    assert_eq!(card.image()[20 * BLOCK_SIZE..23 * BLOCK_SIZE], blocks[..]);
+
+    let address = |index| {
+        card.commands()
+            .iter()
+            .find(|cmd| !cmd.app && cmd.index == index)
+            .map(|cmd| cmd.arg)
+            .unwrap()
+    };
+    assert_eq!(address(17), 3 * address_unit);
+    assert_eq!(address(18), 2 * address_unit);
+    assert_eq!(address(24), 10 * address_unit);
+    assert_eq!(address(25), 20 * address_unit);
+
+    let cmd25 = card
+        .commands()
+        .iter()
+        .position(|cmd| !cmd.app && cmd.index == 25)
+        .unwrap();
+    let acmd23 = &card.commands()[cmd25 - 1];
+    assert!(acmd23.app && acmd23.index == 23 && acmd23.arg == 3);
+}
+
+#[test]
+fn read_write_sdsc_v1() {
+    read_write(SimCardKind::SdscV1, BLOCK_SIZE as u32);
+}
+
+#[test]
+fn read_write_sdsc_v2() {
+    read_write(SimCardKind::SdscV2, BLOCK_SIZE as u32);
+}
+
+#[test]
+fn read_write_sdhc() {
+    read_write(SimCardKind::Sdhc, 1);
+}
+
+#[test]
+fn file_image() {
+    let path = std::env::temp_dir().join(format!("sdmmc-spi-sim-{}.img", std::process::id()));
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT);
+    card.image_mut()[..BLOCK_SIZE].copy_from_slice(&pattern(1));
+    card.save(&path).unwrap();
+
+    let mut card = SimCard::from_file(SimCardKind::SdscV2, &path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, 0).unwrap();
+
+    assert_eq!(buf, pattern(1));
+}
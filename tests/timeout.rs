@@ -0,0 +1,143 @@
+mod common;
+
+use common::{device, pattern, BLOCK_SIZE};
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind},
+    DefaultSdMmcSpiConfig, DiskioDevice, DiskioError, Error, SdMmcSpiConfig,
+};
+
+const BLOCK_COUNT: usize = 2048;
+/// TAAC of 100 us.
+const TAAC_100_US: u8 = 0x0D;
+
+fn ms(ms: u32) -> u64 {
+    u64::from(ms) * 1000
+}
+
+#[test]
+fn slow_init() {
+    let init_time = ms(DefaultSdMmcSpiConfig::INIT_TIMEOUT_MS) * 9 / 10;
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_init_time(init_time);
+    let clock = card.clock();
+    let mut sd = device(&mut card);
+
+    sd.initialize().unwrap();
+
+    assert!(clock.now_us() >= init_time);
+}
+
+#[test]
+fn init_timeout() {
+    let init_time = ms(DefaultSdMmcSpiConfig::INIT_TIMEOUT_MS) * 2;
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_init_time(init_time);
+    let clock = card.clock();
+    let mut sd = device(&mut card);
+
+    assert!(matches!(
+        sd.initialize(),
+        Err(DiskioError::Hardware(Error::CardNotFound))
+    ));
+
+    // Polls at the init clock take time of their bytes besides the delays.
+    let elapsed = clock.now_us();
+    assert!(elapsed >= ms(DefaultSdMmcSpiConfig::INIT_TIMEOUT_MS));
+    assert!(elapsed < ms(DefaultSdMmcSpiConfig::INIT_TIMEOUT_MS) * 11 / 10);
+}
+
+#[test]
+fn slow_write() {
+    let busy_time = ms(DefaultSdMmcSpiConfig::WRITE_TIMEOUT_MS) * 4 / 5;
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_busy_time(busy_time);
+    let clock = card.clock();
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let start = clock.now_us();
+    sd.write(&pattern(0), 0).unwrap();
+    assert!(clock.now_us() - start >= busy_time);
+
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, 0).unwrap();
+    assert_eq!(buf, pattern(0));
+}
+
+#[test]
+fn write_timeout() {
+    let busy_time = ms(DefaultSdMmcSpiConfig::WRITE_TIMEOUT_MS) * 6 / 5;
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT).with_busy_time(busy_time);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert!(matches!(
+        sd.write(&pattern(0), 0),
+        Err(DiskioError::Hardware(Error::TimeoutWaitAvailable))
+    ));
+}
+
+#[test]
+fn csd_timeouts() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT).with_access_time(TAAC_100_US, 25);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let csd = sd.csd().unwrap();
+    let clock_hz = 25_000_000;
+
+    assert_eq!(csd.read_access_time_ns(clock_hz), 200_000);
+    assert_eq!(csd.read_timeout_ms(clock_hz, 100), 20);
+    assert_eq!(csd.write_timeout_ms(clock_hz, 250), 80);
+    assert_eq!(csd.write_timeout_ms(clock_hz, 50), 50);
+}
+
+#[test]
+fn sdsc_read_timeout_from_csd() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT)
+        .with_access_time(TAAC_100_US, 0)
+        .with_read_time(5_000);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, 0).unwrap();
+
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT)
+        .with_access_time(TAAC_100_US, 0)
+        .with_read_time(20_000);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert!(matches!(
+        sd.read(&mut buf, 0),
+        Err(DiskioError::Hardware(Error::TimeoutReadBuffer))
+    ));
+}
+
+#[test]
+fn sdsc_write_timeout_from_csd() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT)
+        .with_access_time(TAAC_100_US, 0)
+        .with_busy_time(60_000);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    assert!(matches!(
+        sd.write(&pattern(0), 0),
+        Err(DiskioError::Hardware(Error::TimeoutWaitAvailable))
+    ));
+}
+
+#[test]
+fn sdhc_ignores_csd_access_time() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT)
+        .with_access_time(TAAC_100_US, 0)
+        .with_read_time(50_000)
+        .with_busy_time(60_000);
+    let mut sd = device(&mut card);
+    sd.initialize().unwrap();
+
+    sd.write(&pattern(0), 0).unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, 0).unwrap();
+    assert_eq!(buf, pattern(0));
+}
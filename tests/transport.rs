@@ -0,0 +1,200 @@
+mod common;
+
+use common::{pattern, BLOCK_SIZE};
+use core::{convert::Infallible, fmt::Debug};
+use embedded_hal_1::{
+    digital::{ErrorType, OutputPin},
+    spi::{self, Operation, SpiDevice},
+};
+use sdmmc_spi::{
+    sim::{SimCard, SimCardKind},
+    DefaultSdMmcSpiConfig, DiskioDevice, SdMmcSpi,
+};
+use switch_hal::OutputSwitch;
+
+const BLOCK_COUNT: usize = 2048;
+
+/// Chip select, records its state.
+#[derive(Default)]
+struct ChipSelect {
+    selected: bool,
+    selects: usize,
+}
+
+impl ChipSelect {
+    fn select(&mut self) {
+        self.selected = true;
+        self.selects += 1;
+    }
+}
+
+impl ErrorType for ChipSelect {
+    type Error = Infallible;
+}
+
+impl OutputPin for ChipSelect {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.select();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.selected = false;
+        Ok(())
+    }
+}
+
+impl OutputSwitch for ChipSelect {
+    type Error = Infallible;
+
+    fn on(&mut self) -> Result<(), Self::Error> {
+        self.select();
+        Ok(())
+    }
+
+    fn off(&mut self) -> Result<(), Self::Error> {
+        self.selected = false;
+        Ok(())
+    }
+}
+
+/// Simulated card counting SPI transactions and recording their sizes in bytes.
+struct CountingCard<'a> {
+    card: &'a mut SimCard,
+    calls: usize,
+    sizes: Vec<usize>,
+}
+
+impl spi::ErrorType for CountingCard<'_> {
+    type Error = Infallible;
+}
+
+impl SpiDevice for CountingCard<'_> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let size = operations
+            .iter()
+            .map(|operation| match operation {
+                Operation::Read(buf) | Operation::TransferInPlace(buf) => buf.len(),
+                Operation::Write(buf) => buf.len(),
+                Operation::Transfer(read, write) => read.len().max(write.len()),
+                Operation::DelayNs(_) => 0,
+            })
+            .sum();
+
+        self.calls += 1;
+        self.sizes.push(size);
+        self.card.transaction(operations)
+    }
+}
+
+fn round_trip<D: DiskioDevice>(sd: &mut D)
+where
+    D::HardwareError: Debug,
+{
+    sd.initialize().unwrap();
+
+    let blocks: Vec<u8> = (10..13).flat_map(pattern).collect();
+    sd.write(&pattern(0), 0).unwrap();
+    sd.write(&blocks, 10).unwrap();
+
+    let mut buf = [0; BLOCK_SIZE];
+    sd.read(&mut buf, 0).unwrap();
+    assert_eq!(buf, pattern(0));
+
+    let mut buf = vec![0; 3 * BLOCK_SIZE];
+    sd.read(&mut buf, 10).unwrap();
+    assert_eq!(buf, blocks);
+}
+
+fn assert_written(card: &SimCard) {
+    assert_eq!(card.block(0), pattern(0));
+    assert_eq!(card.block(11), pattern(11));
+}
+
+#[test]
+fn spi_transport() {
+    let card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let clock = card.clock();
+    let mut sd = SdMmcSpi::<_, _, DefaultSdMmcSpiConfig>::new(card, ChipSelect::default(), clock);
+
+    round_trip(&mut sd);
+
+    let (card, cs) = sd.release().0.release();
+    assert_written(&card);
+    assert!(!cs.selected);
+    assert!(cs.selects > 0);
+}
+
+#[test]
+fn spi_bus_transport() {
+    let mut card = SimCard::new(SimCardKind::SdscV2, BLOCK_COUNT);
+    let clock = card.clock();
+    let mut sd =
+        SdMmcSpi::<_, _, DefaultSdMmcSpiConfig>::new_bus(&mut card, ChipSelect::default(), clock);
+
+    round_trip(&mut sd);
+
+    let (_, cs) = sd.release().0.release();
+    assert!(!cs.selected);
+    assert!(cs.selects > 0);
+    assert_written(&card);
+}
+
+#[test]
+fn bulk_block_transfers() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let clock = card.clock();
+    let mut sd = SdMmcSpi::<_, _, DefaultSdMmcSpiConfig>::new_device(
+        CountingCard {
+            card: &mut card,
+            calls: 0,
+            sizes: Vec::new(),
+        },
+        clock,
+    );
+    sd.initialize().unwrap();
+    sd.write(&pattern(0), 0).unwrap();
+
+    // A byte-at-a-time transfer takes more than BLOCK_SIZE calls per block.
+    let mut buf = [0; BLOCK_SIZE];
+    sd.transport_mut().spi_mut().calls = 0;
+    sd.read(&mut buf, 0).unwrap();
+    assert_eq!(buf, pattern(0));
+    assert!(sd.transport_mut().spi_mut().calls <= 16);
+
+    let mut buf = vec![0; 8 * BLOCK_SIZE];
+    sd.transport_mut().spi_mut().calls = 0;
+    sd.read(&mut buf, 0).unwrap();
+    assert!(sd.transport_mut().spi_mut().calls <= 8 * 8);
+}
+
+#[test]
+fn command_and_data_block_transactions() {
+    let mut card = SimCard::new(SimCardKind::Sdhc, BLOCK_COUNT);
+    let clock = card.clock();
+    let mut sd = SdMmcSpi::<_, _, DefaultSdMmcSpiConfig>::new_device(
+        CountingCard {
+            card: &mut card,
+            calls: 0,
+            sizes: Vec::new(),
+        },
+        clock,
+    );
+    sd.initialize().unwrap();
+
+    // Frame with NCR and R1, token with payload, CRC and data response.
+    sd.transport_mut().spi_mut().sizes.clear();
+    sd.write(&pattern(0), 0).unwrap();
+    let sizes = &sd.transport_mut().spi_mut().sizes;
+    assert!(sizes.contains(&(6 + 2)));
+    assert!(sizes.contains(&(1 + BLOCK_SIZE + 2 + 1)));
+
+    // Frame with NCR and R1, payload with CRC.
+    let mut buf = [0; BLOCK_SIZE];
+    sd.transport_mut().spi_mut().sizes.clear();
+    sd.read(&mut buf, 0).unwrap();
+    assert_eq!(buf, pattern(0));
+    let sizes = &sd.transport_mut().spi_mut().sizes;
+    assert!(sizes.contains(&(6 + 2)));
+    assert!(sizes.contains(&(BLOCK_SIZE + 2)));
+}